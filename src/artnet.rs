@@ -0,0 +1,380 @@
+use std::fmt;
+
+use anyhow::{anyhow, Error};
+
+pub const ARTNET_PORT: u16 = 6454;
+
+// Large enough for any Art-Net packet, so oversize packets are seen as such rather than truncated
+pub const MAX_PACKET_SIZE: usize = 2048;
+pub const MAX_DMX_LENGTH: usize = 512;
+
+const ARTNET_ID: &[u8; 8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const OP_NZS: u16 = 0x5100;
+const OP_SYNC: u16 = 0x5200;
+
+const HEADER_LENGTH: usize = 10;
+const POLL_MIN_LENGTH: usize = 12;
+const DMX_HEADER_LENGTH: usize = 18;
+const SYNC_LENGTH: usize = 14;
+
+// The Art-Net packets the bridge needs to understand, parsed in place without copying or allocating
+#[derive(Debug, PartialEq)]
+pub enum Packet<'a> {
+    Poll(Poll),
+    PollReply,
+    Dmx(Dmx<'a>),
+    Sync,
+    Other(u16),
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Poll {
+    pub protocol_version: u16,
+    pub flags: u8,
+    pub diagnostics_priority: u8,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Dmx<'a> {
+    pub protocol_version: u16,
+    pub sequence: u8,
+    pub physical: u8,
+    pub start_code: u8,
+    pub port_address: PortAddress,
+    pub data: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    NotArtNet,
+    TooShort { opcode: u16, length: usize },
+    DmxTooLong(usize),
+    DmxTruncated { expected: usize, received: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::NotArtNet => write!(f, "Not an Art-Net packet"),
+            ParseError::TooShort { opcode, length } =>
+                write!(f, "Art-Net packet with opcode {:#06x} is too short ({} bytes)", opcode, length),
+            ParseError::DmxTooLong(length) =>
+                write!(f, "ArtDmx length {} is longer than {} channels", length, MAX_DMX_LENGTH),
+            ParseError::DmxTruncated { expected, received } =>
+                write!(f, "ArtDmx packet has {} channels of data but its length field says {}", received, expected),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(buffer: &[u8]) -> Result<Packet<'_>, ParseError> {
+    if buffer.len() < HEADER_LENGTH || &buffer[..8] != ARTNET_ID {
+        return Err(ParseError::NotArtNet);
+    }
+
+    // The opcode is little-endian; every other multi-byte field is big-endian
+    let opcode = u16::from_le_bytes([buffer[8], buffer[9]]);
+    let too_short = ParseError::TooShort { opcode, length: buffer.len() };
+
+    match opcode {
+        OP_POLL => {
+            if buffer.len() < POLL_MIN_LENGTH {
+                return Err(too_short);
+            }
+            // Flags and priority were added in later protocol revisions, so allow them to be missing
+            Ok(Packet::Poll(Poll {
+                protocol_version: u16::from_be_bytes([buffer[10], buffer[11]]),
+                flags: buffer.get(12).copied().unwrap_or(0),
+                diagnostics_priority: buffer.get(13).copied().unwrap_or(0),
+            }))
+        },
+        OP_POLL_REPLY => Ok(Packet::PollReply),
+        // ArtNzs carries data with a non-zero start code, in the same layout as ArtDmx except
+        // that the start code takes the place of the physical port
+        OP_DMX | OP_NZS => {
+            if buffer.len() < DMX_HEADER_LENGTH {
+                return Err(too_short);
+            }
+            let length = u16::from_be_bytes([buffer[16], buffer[17]]) as usize;
+            if length > MAX_DMX_LENGTH {
+                return Err(ParseError::DmxTooLong(length));
+            }
+            // Odd lengths are accepted, and anything after the declared length is ignored
+            let data = &buffer[DMX_HEADER_LENGTH..];
+            if data.len() < length {
+                return Err(ParseError::DmxTruncated { expected: length, received: data.len() });
+            }
+            Ok(Packet::Dmx(Dmx {
+                protocol_version: u16::from_be_bytes([buffer[10], buffer[11]]),
+                sequence: buffer[12],
+                physical: if opcode == OP_DMX { buffer[13] } else { 0 },
+                start_code: if opcode == OP_DMX { 0 } else { buffer[13] },
+                port_address: PortAddress::from(u16::from_le_bytes([buffer[14], buffer[15]])),
+                data: &data[..length],
+            }))
+        },
+        OP_SYNC => {
+            if buffer.len() < SYNC_LENGTH {
+                return Err(too_short);
+            }
+            Ok(Packet::Sync)
+        },
+        _ => Ok(Packet::Other(opcode)),
+    }
+}
+
+// An Art-Net 4 Port-Address: a 15-bit universe number, made up of a 7-bit Net, a 4-bit Sub-Net
+// and a 4-bit Universe. It can be written as net:subnet:universe, or as a flat value 0-32767.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct PortAddress(u16);
+
+impl PortAddress {
+    pub fn new(network: u16, subnet: u16, universe: u16) -> Result<Self, Error> {
+        if network > 0x7F {
+            return Err(anyhow!("Art-Net network {} is out of range (must be 0-127)", network));
+        }
+        if subnet > 0x0F {
+            return Err(anyhow!("Art-Net subnet {} is out of range (must be 0-15)", subnet));
+        }
+        if universe > 0x0F {
+            return Err(anyhow!("Art-Net universe {} is out of range (must be 0-15)", universe));
+        }
+        Ok(PortAddress((network << 8) | (subnet << 4) | universe))
+    }
+
+    pub fn from_flat(value: u16) -> Result<Self, Error> {
+        if value > 0x7FFF {
+            return Err(anyhow!("Art-Net universe {} is out of range (must be 0-32767)", value));
+        }
+        Ok(PortAddress(value))
+    }
+
+    pub fn value(self) -> u16 {
+        self.0
+    }
+
+    pub fn network(self) -> u8 {
+        ((self.0 >> 8) & 0x7F) as u8
+    }
+
+    pub fn subnet(self) -> u8 {
+        ((self.0 >> 4) & 0x0F) as u8
+    }
+
+    pub fn universe(self) -> u8 {
+        (self.0 & 0x0F) as u8
+    }
+}
+
+// Port-Addresses received from the network are 16 bits wide, but the top bit isn't part of the address
+impl From<u16> for PortAddress {
+    fn from(value: u16) -> Self {
+        PortAddress(value & 0x7FFF)
+    }
+}
+
+impl fmt::Display for PortAddress {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.network(), self.subnet(), self.universe())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use artnet_protocol::{ArtCommand, ARTNET_PROTOCOL_VERSION};
+    use crate::utils;
+
+    // ArtPoll, protocol 14, asking for replies on change, diagnostics priority low
+    const POLL: [u8; 14] = [
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x20, 0x00, 0x0e, 0x02, 0x10,
+    ];
+
+    // An Art-Net 4 ArtPoll with the fields added after the priority: a target port address range of
+    // 0:0:1 to 0:0:16, ESTA manufacturer code 0x4142 and OEM code 0x0001
+    const POLL_ARTNET_4: [u8; 22] = [
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x20, 0x00, 0x0e, 0x06, 0x10,
+        0x00, 0x10, 0x00, 0x01, 0x41, 0x42, 0x00, 0x01,
+    ];
+
+    // An older ArtPoll without the flags and priority fields
+    const POLL_SHORT: [u8; 12] = [
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x20, 0x00, 0x0e,
+    ];
+
+    // ArtSync, protocol 14
+    const SYNC: [u8; 14] = [
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x52, 0x00, 0x0e, 0x00, 0x00,
+    ];
+
+    // ArtDmx header for sequence 0x2a, physical port 1, net 1, subnet 2, universe 5
+    const DMX_HEADER: [u8; 16] = [
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x50, 0x00, 0x0e, 0x2a, 0x01, 0x25, 0x01,
+    ];
+
+    // ArtNzs for sequence 0x2a, start code 0x17 (text), net 1, subnet 2, universe 5, carrying "Hi"
+    const NZS: [u8; 20] = [
+        0x41, 0x72, 0x74, 0x2d, 0x4e, 0x65, 0x74, 0x00, 0x00, 0x51, 0x00, 0x0e, 0x2a, 0x17, 0x25, 0x01,
+        0x00, 0x02, 0x48, 0x69,
+    ];
+
+    fn dmx_packet(length: u16, data: &[u8]) -> Vec<u8> {
+        let mut packet = DMX_HEADER.to_vec();
+        packet.extend_from_slice(&length.to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn test_parse_poll_and_sync() {
+        assert_eq!(parse(&POLL), Ok(Packet::Poll(Poll { protocol_version: 14, flags: 0x02, diagnostics_priority: 0x10 })));
+        assert_eq!(parse(&POLL_SHORT), Ok(Packet::Poll(Poll { protocol_version: 14, flags: 0, diagnostics_priority: 0 })));
+        assert_eq!(parse(&POLL_ARTNET_4), Ok(Packet::Poll(Poll { protocol_version: 14, flags: 0x06, diagnostics_priority: 0x10 })));
+        assert_eq!(parse(&SYNC), Ok(Packet::Sync));
+        assert_eq!(parse(&SYNC[..12]), Err(ParseError::TooShort { opcode: OP_SYNC, length: 12 }));
+    }
+
+    #[test]
+    fn test_parse_dmx() {
+        let data: Vec<u8> = (0..=255).chain(0..=255).collect();
+
+        let packet = dmx_packet(512, &data);
+        match parse(&packet) {
+            Ok(Packet::Dmx(dmx)) => {
+                assert_eq!(dmx.protocol_version, 14);
+                assert_eq!(dmx.sequence, 0x2a);
+                assert_eq!(dmx.physical, 1);
+                assert_eq!(dmx.start_code, 0);
+                assert_eq!(dmx.port_address, PortAddress::new(1, 2, 5).unwrap());
+                assert_eq!(dmx.data, &data[..]);
+            },
+            other => panic!("Expected ArtDmx, got {:?}", other),
+        }
+
+        // Odd lengths are allowed, and trailing padding is ignored
+        let packet = dmx_packet(3, &[10, 20, 30, 0]);
+        match parse(&packet) {
+            Ok(Packet::Dmx(dmx)) => assert_eq!(dmx.data, &[10, 20, 30]),
+            other => panic!("Expected ArtDmx, got {:?}", other),
+        }
+
+        assert_eq!(parse(&dmx_packet(514, &[0; 514])), Err(ParseError::DmxTooLong(514)));
+        assert_eq!(parse(&dmx_packet(512, &[0; 100])), Err(ParseError::DmxTruncated { expected: 512, received: 100 }));
+        assert_eq!(parse(&DMX_HEADER), Err(ParseError::TooShort { opcode: OP_DMX, length: 16 }));
+    }
+
+    #[test]
+    fn test_parse_nzs() {
+        match parse(&NZS) {
+            Ok(Packet::Dmx(dmx)) => {
+                assert_eq!(dmx.sequence, 0x2a);
+                assert_eq!(dmx.physical, 0);
+                assert_eq!(dmx.start_code, 0x17);
+                assert_eq!(dmx.port_address, PortAddress::new(1, 2, 5).unwrap());
+                assert_eq!(dmx.data, &[72, 105]);
+            },
+            other => panic!("Expected ArtNzs, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_other_encoder() {
+        // Packets from another implementation, rather than laid out by hand alongside the parser.
+        // artnet_protocol 0.2.0 writes 1337 as its protocol version, which is passed through as is.
+        let version = u16::from_be_bytes(ARTNET_PROTOCOL_VERSION);
+        let poll = ArtCommand::Poll(artnet_protocol::Poll::default()).into_buffer().unwrap();
+        assert_eq!(parse(&poll), Ok(Packet::Poll(Poll { protocol_version: version, flags: 0, diagnostics_priority: 0x80 })));
+
+        let data: Vec<u8> = (0..=255).chain(0..=255).collect();
+        let dmx = utils::dmx_output(PortAddress::new(1, 2, 5).unwrap(), 0x2a, 1, &data).into_buffer().unwrap();
+        match parse(&dmx) {
+            Ok(Packet::Dmx(dmx)) => {
+                assert_eq!((dmx.protocol_version, dmx.sequence, dmx.physical, dmx.start_code), (version, 0x2a, 1, 0));
+                assert_eq!(dmx.port_address, PortAddress::new(1, 2, 5).unwrap());
+                assert_eq!(dmx.data, &data[..]);
+            },
+            other => panic!("Expected ArtDmx, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_captures() {
+        // Packets captured from consoles, listed in captures/README.md. Fields are read straight from
+        // the bytes here, so a mistake in the parser's byte order or odd-length handling shows up.
+        let directory = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("captures");
+        for entry in std::fs::read_dir(&directory).unwrap() {
+            let path = entry.unwrap().path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("bin") {
+                continue;
+            }
+            let packet = std::fs::read(&path).unwrap();
+            match parse(&packet) {
+                Ok(Packet::Dmx(dmx)) => {
+                    let length = u16::from_be_bytes([packet[16], packet[17]]) as usize;
+                    let port_address = u16::from_le_bytes([packet[14], packet[15]]);
+                    assert_eq!(dmx.data, &packet[DMX_HEADER_LENGTH..DMX_HEADER_LENGTH + length], "{:?}", path);
+                    assert_eq!(dmx.port_address, PortAddress::from(port_address), "{:?}", path);
+                },
+                Ok(Packet::Poll(poll)) if packet.len() >= 14 => {
+                    assert_eq!((poll.flags, poll.diagnostics_priority), (packet[12], packet[13]), "{:?}", path);
+                },
+                Ok(_) => {},
+                Err(e) => panic!("Could not parse {:?}: {}", path, e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_other() {
+        let mut address = POLL.to_vec();
+        address[8..10].copy_from_slice(&0x6000u16.to_le_bytes());
+        assert_eq!(parse(&address), Ok(Packet::Other(0x6000)));
+
+        assert_eq!(parse(b"Art-Net"), Err(ParseError::NotArtNet));
+        assert_eq!(parse(b"GET / HTTP/1.1\r\n"), Err(ParseError::NotArtNet));
+        assert_eq!(parse(&[]), Err(ParseError::NotArtNet));
+    }
+
+    #[test]
+    fn test_parse_garbage() {
+        // Every truncation of each packet, and every single-byte corruption of an ArtDmx packet's
+        // header, either parses or fails cleanly
+        let dmx = dmx_packet(4, &[1, 2, 3, 4]);
+        for packet in [&POLL[..], &SYNC[..], &dmx[..]] {
+            for length in 0..=packet.len() {
+                let _ = parse(&packet[..length]);
+            }
+        }
+        for index in 0..DMX_HEADER_LENGTH {
+            for value in [0x00, 0x01, 0x7f, 0x80, 0xff] {
+                let mut corrupted = dmx.clone();
+                corrupted[index] = value;
+                if let Ok(Packet::Dmx(parsed)) = parse(&corrupted) {
+                    assert!(parsed.data.len() <= 4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_port_address() {
+        let address = PortAddress::new(1, 2, 12).unwrap();
+        assert_eq!(address.value(), 0x12C);
+        assert_eq!((address.network(), address.subnet(), address.universe()), (1, 2, 12));
+        assert_eq!(address, PortAddress::from_flat(300).unwrap());
+        assert_eq!(address.to_string(), "1:2:12");
+
+        let highest = PortAddress::from_flat(32767).unwrap();
+        assert_eq!((highest.network(), highest.subnet(), highest.universe()), (127, 15, 15));
+        assert_eq!(PortAddress::from(0x8005), PortAddress::from_flat(5).unwrap());
+
+        PortAddress::new(128, 0, 0).expect_err("Expected network 128 to fail");
+        PortAddress::new(0, 16, 0).expect_err("Expected subnet 16 to fail");
+        PortAddress::new(0, 0, 16).expect_err("Expected universe 16 to fail");
+        PortAddress::from_flat(32768).expect_err("Expected universe 32768 to fail");
+    }
+}
@@ -0,0 +1,138 @@
+use crate::config::{Command, Configuration, Severity, ValidationIssue};
+use crate::kinet;
+
+// Run a subcommand against a loaded configuration, returning whether it found no errors
+pub fn run(command: &Command, cfg: &Configuration) -> bool {
+    let issues = cfg.validate();
+    if let Command::DumpConfig = command {
+        dump_config(cfg);
+    }
+    report(&issues)
+}
+
+fn dump_config(cfg: &Configuration) {
+    println!("Art-Net receive address: {} ({})", cfg.artnet_receive_ip, cfg.artnet_receive_addr);
+    println!("KiNET send address:      {} ({})", cfg.kinet_send_ip, cfg.kinet_send_addr);
+    if let Some(osc_port) = cfg.osc_port {
+        println!("OSC port:                {}", osc_port);
+    }
+    if let Some(http_port) = cfg.http_port {
+        println!("HTTP address:            {} ({}) port {}", cfg.http_ip, cfg.http_addr, http_port);
+    }
+    if !cfg.scenes.is_empty() {
+        let mut names: Vec<&String> = cfg.scenes.keys().collect();
+        names.sort();
+        println!("Scenes:                  {}", names.iter().map(|name| name.as_str()).collect::<Vec<&str>>().join(", "));
+    }
+    println!();
+    println!("{:<18} {:<32} {:<22} {:<5} {:<8} {:<12} Start code", "Art-Net", "KiNET destination", "Socket address", "Port", "Message", "Channels");
+
+    let mut universes: Vec<&u16> = cfg.destinations.kinet.keys().collect();
+    universes.sort();
+    for universe in universes {
+        let destination = &cfg.destinations.kinet[universe];
+        let message = if destination.kinet_port == 0 { "DmxOut" } else { "PortOut" };
+        let channels = match destination.kinet_channels {
+            Some(channels) => channels.to_string(),
+            None if destination.kinet_port == 0 && !destination.kinet_trim_dmx_out => "512".to_string(),
+            None => "as received".to_string(),
+        };
+        let start_code = match (destination.kinet_port, destination.kinet_start_code) {
+            (0, _) => "-".to_string(),
+            (_, start_code) => format!("{:#06x}", start_code.unwrap_or(kinet::DEFAULT_START_CODE)),
+        };
+        println!("{:<18} {:<32} {:<22} {:<5} {:<8} {:<12} {}",
+            destination.artnet_address(), destination.kinet_address, destination.kinet_socket_addr,
+            destination.kinet_port, message, channels, start_code);
+    }
+    println!();
+
+    if !cfg.destinations.opc.is_empty() {
+        println!("{:<18} {:<32} {:<8} Pixels", "Art-Net", "OPC server", "Channel");
+        let mut universes: Vec<&u16> = cfg.destinations.opc.keys().collect();
+        universes.sort();
+        for universe in universes {
+            let destination = &cfg.destinations.opc[universe];
+            let pixels = match destination.pixels {
+                Some(_) => format!("{}-{}", destination.pixel_range().start, destination.pixel_range().end - 1),
+                None => format!("from {}", destination.pixel_offset),
+            };
+            println!("{:<18} {:<32} {:<8} {}",
+                destination.artnet_address(), destination.opc_address, destination.opc_channel, pixels);
+        }
+        println!();
+    }
+
+    if !cfg.destinations.sacn.is_empty() {
+        println!("sACN source name: {}", cfg.sacn_source_name);
+        println!("sACN CID:         {}", format_uuid(&cfg.sacn_cid));
+        println!();
+        println!("{:<18} {:<14} {:<22} Priority", "Art-Net", "sACN universe", "Socket address");
+        let mut universes: Vec<&u16> = cfg.destinations.sacn.keys().collect();
+        universes.sort();
+        for universe in universes {
+            let destination = &cfg.destinations.sacn[universe];
+            println!("{:<18} {:<14} {:<22} {}",
+                destination.artnet_address(), destination.sacn_universe, destination.sacn_socket_addr,
+                destination.sacn_priority.unwrap_or(cfg.sacn_priority));
+        }
+        println!();
+    }
+
+    if !cfg.destinations.artnet.is_empty() {
+        println!("{:<18} {:<32} {:<22} Universe", "Art-Net", "Art-Net node", "Socket address");
+        let mut universes: Vec<&u16> = cfg.destinations.artnet.keys().collect();
+        universes.sort();
+        for universe in universes {
+            let destination = &cfg.destinations.artnet[universe];
+            println!("{:<18} {:<32} {:<22} {} ({})",
+                destination.artnet_address(), destination.artnet_node, destination.artnet_socket_addr,
+                destination.artnet_output_universe, destination.artnet_output_universe.value());
+        }
+        println!();
+    }
+
+    if !cfg.kinet_inputs.is_empty() {
+        println!("{:<12} {:<16} {:<32} Art-Net", "KiNET port", "Controller", "Art-Net node");
+        for input in &cfg.kinet_inputs {
+            let controller = input.kinet_source.map_or("any".to_string(), |source| source.to_string());
+            println!("{:<12} {:<16} {:<32} {} ({})",
+                input.kinet_port, controller, input.artnet_node, input.port_address, input.port_address.value());
+        }
+        println!();
+    }
+
+    if !cfg.destinations.ddp.is_empty() {
+        println!("{:<18} {:<32} {:<22} Pixels", "Art-Net", "DDP device", "Socket address");
+        let mut universes: Vec<&u16> = cfg.destinations.ddp.keys().collect();
+        universes.sort();
+        for universe in universes {
+            let destination = &cfg.destinations.ddp[universe];
+            println!("{:<18} {:<32} {:<22} {}-{}",
+                destination.artnet_address(), destination.ddp_device, destination.ddp_socket_addr,
+                destination.pixel_range().start, destination.pixel_range().end - 1);
+        }
+        println!();
+    }
+}
+
+fn format_uuid(uuid: &[u8; 16]) -> String {
+    let hex: String = uuid.iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{}-{}-{}-{}-{}", &hex[..8], &hex[8..12], &hex[12..16], &hex[16..20], &hex[20..])
+}
+
+fn report(issues: &[ValidationIssue]) -> bool {
+    for issue in issues {
+        println!("{}", issue);
+    }
+
+    let errors = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    let warnings = issues.len() - errors;
+    if errors == 0 {
+        println!("Configuration is valid ({} warnings)", warnings);
+    } else {
+        println!("Configuration has {} errors and {} warnings", errors, warnings);
+    }
+
+    errors == 0
+}
@@ -1,16 +1,27 @@
 use structopt::StructOpt;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
+use serde::de::{self, MapAccess, Visitor};
 use log::Level;
 use anyhow::Error;
-use std::fs::File;
-use std::io::BufReader;
+use std::env;
+use std::fs;
 use std::path::Path;
 use anyhow::{anyhow, Result};
-use std::collections::HashMap;
-use std::net::{Ipv4Addr, SocketAddr, ToSocketAddrs};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, ToSocketAddrs};
 use std::str::FromStr;
+use std::fmt;
+use std::ops::Range;
+use if_addrs::Ifv4Addr;
+use crate::artnet::{self, PortAddress, MAX_DMX_LENGTH};
+use crate::control::Scene;
+use crate::ddp;
+use crate::kinet;
+use crate::opc;
+use crate::sacn;
+use crate::utils;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct KinetDestination {
     pub artnet_network: u16,
     pub artnet_subnet: u8,
@@ -18,34 +29,301 @@ pub struct KinetDestination {
     pub kinet_address: String,
     pub kinet_socket_addr: SocketAddr,
     pub kinet_port: u8,
+    // Number of channels to send; when unset, as many as arrived in the Art-Net packet
+    pub kinet_channels: Option<u16>,
+    // Whether DmxOut packets can be cut short, rather than always carrying 512 channels
+    pub kinet_trim_dmx_out: bool,
+    // Start code for PortOut packets carrying ordinary level data, in place of the default
+    pub kinet_start_code: Option<u16>,
+}
+
+// Sends a universe to an Open Pixel Control server, such as fcserver, as RGB pixels starting at
+// pixel_offset on an OPC channel. The address is kept as written and resolved when connecting.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OpcDestination {
+    pub port_address: PortAddress,
+    pub opc_address: String,
+    pub opc_channel: u8,
+    pub pixel_offset: u16,
+    pub pixels: Option<u16>,
+}
+
+// Re-sends a universe as sACN (E1.31), to the universe's multicast group or a unicast address
+#[derive(Debug, Clone, PartialEq)]
+pub struct SacnDestination {
+    pub port_address: PortAddress,
+    pub sacn_universe: u16,
+    pub sacn_unicast: Option<String>,
+    pub sacn_socket_addr: SocketAddr,
+    pub sacn_priority: Option<u8>,
+}
+
+// Re-sends a universe as ArtDmx to an Art-Net node, on the same or a renumbered universe
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArtnetDestination {
+    pub port_address: PortAddress,
+    pub artnet_node: String,
+    pub artnet_socket_addr: SocketAddr,
+    pub artnet_output_universe: PortAddress,
+}
+
+// Sends a universe to a DDP device as RGB pixels starting at pixel_offset, as part of a frame made
+// up of every universe mapped to that device
+#[derive(Debug, Clone, PartialEq)]
+pub struct DdpDestination {
+    pub port_address: PortAddress,
+    pub ddp_device: String,
+    pub ddp_socket_addr: SocketAddr,
+    pub pixel_offset: u32,
+    pub pixels: Option<u16>,
+}
+
+// Re-sends KiNET received from a controller as ArtDmx, for one KiNET port (0 for DmxOut), optionally
+// only from one controller
+#[derive(Debug, Clone, PartialEq)]
+pub struct KinetSource {
+    pub kinet_source: Option<Ipv4Addr>,
+    pub kinet_port: u8,
+    pub port_address: PortAddress,
+    pub artnet_node: String,
+    pub artnet_socket_addr: SocketAddr,
+}
+
+// Every output Art-Net universes are routed to, keyed by 15-bit Port-Address. A universe can go
+// to one destination of each type, so the same output can drive both KiNET fixtures and LED strips.
+#[derive(Debug, Default)]
+pub struct Destinations {
+    pub kinet: HashMap<u16, KinetDestination>,
+    pub opc: HashMap<u16, OpcDestination>,
+    pub sacn: HashMap<u16, SacnDestination>,
+    pub artnet: HashMap<u16, ArtnetDestination>,
+    pub ddp: HashMap<u16, DdpDestination>,
+    // Universes mapped to more than one place, which are reported by validate
+    pub conflicts: Vec<String>,
+}
+
+// A destination described for people: its protocol, a name that's also used to count what's sent to
+// it, and where it's sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct DestinationSummary {
+    pub port_address: u16,
+    pub protocol: &'static str,
+    pub name: String,
+    pub address: String,
+}
+
+#[derive(Debug)]
+pub enum Destination {
+    Kinet(KinetDestination),
+    Opc(OpcDestination),
+    Sacn(SacnDestination),
+    Artnet(ArtnetDestination),
+    Ddp(DdpDestination),
+}
+
+// A named KiNET power/data supply, declared once in a config file and referenced by mappings
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Supply {
+    pub ip: String,
+    #[serde(default = "default_supply_ports")]
+    pub ports: u8,
+    #[serde(default)]
+    pub protocol: Option<KinetProtocol>,
+    // Older supplies expect every KiNET v1 DmxOut packet to carry 512 channels, so only send
+    // shorter ones to supplies known to accept them
+    #[serde(default)]
+    pub trim_dmx_out: bool,
+}
+
+fn default_supply_ports() -> u8 { 16 }
+
+#[derive(Debug, Deserialize, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum KinetProtocol {
+    V1,
+    V2,
+}
+
+// A named Open Pixel Control server, as host or host:port
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct OpcServer {
+    pub address: String,
+}
+
+// A named output port on a supply
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Fixture {
+    pub supply: String,
+    #[serde(default)]
+    pub port: u8,
+    #[serde(default)]
+    pub channels: Option<u16>,
+    #[serde(default)]
+    pub start_code: Option<u16>,
+}
+
+// KiNET to receive from a controller and send on as Art-Net, as written in a config file
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct KinetInput {
+    #[serde(default)]
+    pub source: Option<String>,
+    #[serde(default)]
+    pub port: u8,
+    pub universe: UniverseSpec,
+    pub artnet_node: String,
+}
+
+// A scene as written in a config file, with levels for each universe listed from channel 1
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SceneDefinition {
+    #[serde(default)]
+    pub master: Option<f32>,
+    #[serde(default)]
+    pub blackout: Option<bool>,
+    #[serde(default)]
+    pub levels: HashMap<String, Vec<u8>>,
+}
+
+// Mappings can be given either as a map-string, or in a config file as an object that refers to
+// a supply or fixture by name
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mapping {
+    MapString(String),
+    Named(NamedMapping),
+}
+
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct NamedMapping {
+    pub universe: UniverseSpec,
+    pub supply: Option<String>,
+    pub fixture: Option<String>,
+    pub port: Option<u8>,
+    pub channels: Option<u16>,
+    pub start_code: Option<u16>,
+    pub opc_server: Option<String>,
+    pub opc_channel: Option<u8>,
+    pub pixel_offset: Option<u16>,
+    pub pixels: Option<u16>,
+    pub sacn_universe: Option<u16>,
+    pub sacn_unicast: Option<String>,
+    pub sacn_priority: Option<u8>,
+    pub artnet_node: Option<String>,
+    pub artnet_universe: Option<UniverseSpec>,
+    pub ddp_device: Option<String>,
+    pub ddp_universes: Option<u16>,
+}
+
+// Art-Net universes in named mappings can be written as a number or in the net:subnet:universe form
+#[derive(Debug, Deserialize, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum UniverseSpec {
+    Number(u16),
+    Text(String),
+}
+
+const ENV_PREFIX: &str = "ARTNET_BRIDGE_";
+const DEFAULT_SACN_SOURCE_NAME: &str = "ArtNet/KiNET Bridge";
+
+// Configuration files are detected by extension; anything that isn't TOML or YAML is read as JSON
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ConfigFormat {
+    Json,
+    Toml,
+    Yaml,
 }
 
 pub struct Configuration {
     pub artnet_receive_ip: String,
+    pub artnet_receive_addr: Ipv4Addr,
     pub kinet_send_ip: String,
-    pub kinet_destinations: HashMap<u16, KinetDestination>,
+    pub kinet_send_addr: Ipv4Addr,
+    pub destinations: Destinations,
+    pub kinet_inputs: Vec<KinetSource>,
+    pub kinet_flags: u16,
+    pub kinet_timer: i32,
+    pub sacn_source_name: String,
+    pub sacn_cid: [u8; 16],
+    pub sacn_priority: u8,
+    pub osc_port: Option<u16>,
+    pub http_port: Option<u16>,
+    pub http_ip: String,
+    pub http_addr: Ipv4Addr,
+    pub scenes: HashMap<String, Scene>,
     pub verbosity: i8,
 }
 
-#[derive(Debug, StructOpt, Deserialize, Default)]
+#[derive(Debug, StructOpt, Deserialize, Default, Clone)]
 /// Map Art-Net universes to KiNET PDS endpoints
 pub struct UserConfiguration {
-    /// The IPv4 network address where Art-Net packets will be received
+    /// The IPv4 network address or interface name where Art-Net packets will be received. Use 0.0.0.0 to receive on all interfaces
     #[structopt(short = "a", display_order = 1)]
     pub artnet_receive_ip: Option<String>,
-    /// The IPv4 network address that KiNET packets should be sent from   
+    /// The IPv4 network address or interface name that KiNET packets should be sent from. Use 0.0.0.0 to let the OS choose
     #[structopt(short = "k", display_order = 2)]
     pub kinet_send_ip: Option<String>,
     /// Map a single Art-Net universe data to a KiNET destination. Each map-string contains an Art-Net source universe and
-    /// a KiNET destination IPv4 address, with optional KiNET output port, all separated by colons.
-    /// Art-Net source universes can be specified as just a single universe value, or as a network, subnet, and universe.
+    /// a KiNET destination IPv4 address or hostname, with optional KiNET output port, all separated by colons.
+    /// Hostnames are re-resolved periodically, so supplies can be addressed by DHCP or mDNS name.
+    /// Art-Net source universes can be specified as a single 15-bit Port-Address from 0 to 32767, or as a network (0-127),
+    /// subnet (0-15), and universe (0-15).
     /// 1:0:15:10.0.0.1:3 would listen for Art-Net output commands destined for network 1, subnet 0, universe 15,
     /// and resend that output data to the KiNET PDS at 10.0.0.1, for output on KiNET port 3.
     /// Specifying no port, or 0, will send a KiNET v1 message; specifying port 1-16 will send a KiNET v2 PORTOUT message.
     /// If any network/subnet/universe values are not provided, they will be assumed to be 0, so the following are all valid:
     /// -m 10.0.0.4 -m 3:192.168.10.100 -m 1:4:13:10.0.1.4 -m 192.168.0.15:10 -m 1:1:10.0.0.2:2
-    #[structopt(short = "m", long = "mapping", value_name = "map-string", display_order = 3)]
-    pub mappings: Option<Vec<String>>,
+    #[structopt(short = "m", long = "mapping", value_name = "map-string", display_order = 3, number_of_values = 1, parse(from_str))]
+    pub mappings: Option<Vec<Mapping>>,
+    /// Named KiNET supplies that mappings can refer to. Config file only.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub supplies: HashMap<String, Supply>,
+    /// Named supply outputs that mappings can refer to. Config file only.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub fixtures: HashMap<String, Fixture>,
+    /// Named Open Pixel Control servers that mappings can refer to. Config file only.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub opc_servers: HashMap<String, OpcServer>,
+    /// KiNET ports to receive from controllers and re-send as Art-Net. Config file only.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub kinet_inputs: Vec<KinetInput>,
+    /// Named scenes that can be recalled from the control interfaces. Config file only.
+    #[structopt(skip)]
+    #[serde(default)]
+    pub scenes: HashMap<String, SceneDefinition>,
+    /// Flags to set in the header of every KiNET packet, as a decimal or 0x-prefixed hex value. Defaults to 0
+    #[structopt(long = "kinet-flags", value_name = "flags", parse(try_from_str = utils::parse_u16))]
+    pub kinet_flags: Option<u16>,
+    /// Value of the timer field in KiNET v1 DmxOut packets. Defaults to -1
+    #[structopt(long = "kinet-timer", value_name = "timer", allow_hyphen_values = true)]
+    pub kinet_timer: Option<i32>,
+    /// Source name sent in sACN packets, up to 63 bytes. Defaults to ArtNet/KiNET Bridge
+    #[structopt(long = "sacn-source-name", value_name = "name")]
+    pub sacn_source_name: Option<String>,
+    /// Component identifier (CID) sent in sACN packets, as a UUID. Defaults to one derived from the source name
+    #[structopt(long = "sacn-cid", value_name = "uuid")]
+    pub sacn_cid: Option<String>,
+    /// Priority of sACN output from 0 to 200, unless a mapping sets its own. Defaults to 100
+    #[structopt(long = "sacn-priority", value_name = "priority")]
+    pub sacn_priority: Option<u8>,
+    /// UDP port to listen for OSC control messages on, at the Art-Net receive address. OSC is off unless a port is given
+    #[structopt(long = "osc-port", value_name = "port")]
+    pub osc_port: Option<u16>,
+    /// TCP port to serve the HTTP status and control API on. HTTP is off unless a port is given
+    #[structopt(long = "http-port", value_name = "port")]
+    pub http_port: Option<u16>,
+    /// The IPv4 network address or interface name to serve the HTTP API on. Defaults to the Art-Net receive address; use
+    /// 127.0.0.1 to only allow access from this machine
+    #[structopt(long = "http-ip", value_name = "address")]
+    pub http_ip: Option<String>,
     /// Path to a file containing configuration options. All command-line options can be specified in the config file;
     /// command-line options will override options in file where there's a conflict. 
     #[structopt(short = "f", long = "file")]
@@ -59,130 +337,1145 @@ pub struct UserConfiguration {
     #[structopt(long, short = "q", parse(from_occurrences), conflicts_with = "verbose")]
     #[serde(default)]
     pub quiet: i8,
+    #[structopt(subcommand)]
+    #[serde(skip)]
+    pub command: Option<Command>,
+}
+
+#[derive(Debug, StructOpt, Clone, PartialEq)]
+pub enum Command {
+    /// Check the configuration for errors and exit, without binding any sockets
+    Validate,
+    /// Print the fully resolved configuration and mapping table and exit, without binding any sockets
+    DumpConfig,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Warning,
+    Error,
+}
+
+// A problem found in a configuration that loaded successfully
+#[derive(Debug, Clone, PartialEq)]
+pub struct ValidationIssue {
+    pub severity: Severity,
+    pub message: String,
+}
+
+impl KinetDestination {
+    pub fn port_address(&self) -> PortAddress {
+        PortAddress::from((self.artnet_network << 8) | ((self.artnet_subnet as u16) << 4) | self.artnet_universe as u16)
+    }
+
+    pub fn artnet_address(&self) -> String {
+        format!("{} ({})", self.port_address(), self.port_address().value())
+    }
+}
+
+impl fmt::Display for KinetDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} port {}", self.kinet_address, self.kinet_port)
+    }
+}
+
+impl OpcDestination {
+    pub fn artnet_address(&self) -> String {
+        format!("{} ({})", self.port_address, self.port_address.value())
+    }
+
+    // The pixels this destination covers on its OPC channel, assuming a full universe if unset
+    pub fn pixel_range(&self) -> std::ops::Range<usize> {
+        let start = self.pixel_offset as usize;
+        start..start + self.pixels.unwrap_or(opc::MAX_PIXELS) as usize
+    }
+}
+
+impl fmt::Display for OpcDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "OPC server {} channel {}", self.opc_address, self.opc_channel)
+    }
+}
+
+impl SacnDestination {
+    pub fn artnet_address(&self) -> String {
+        format!("{} ({})", self.port_address, self.port_address.value())
+    }
+}
+
+impl fmt::Display for SacnDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.sacn_unicast {
+            Some(sacn_unicast) => write!(f, "sACN universe {} at {}", self.sacn_universe, sacn_unicast),
+            None => write!(f, "sACN universe {} at {}", self.sacn_universe, self.sacn_socket_addr.ip()),
+        }
+    }
+}
+
+impl ArtnetDestination {
+    pub fn artnet_address(&self) -> String {
+        format!("{} ({})", self.port_address, self.port_address.value())
+    }
+}
+
+impl fmt::Display for ArtnetDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Art-Net node {} universe {}", self.artnet_node, self.artnet_output_universe)
+    }
+}
+
+impl DdpDestination {
+    pub fn artnet_address(&self) -> String {
+        format!("{} ({})", self.port_address, self.port_address.value())
+    }
+
+    // The pixels this destination covers in its device's frame, assuming a full universe if unset
+    pub fn pixel_range(&self) -> std::ops::Range<usize> {
+        let start = self.pixel_offset as usize;
+        start..start + self.pixels.unwrap_or(opc::MAX_PIXELS) as usize
+    }
+}
+
+impl fmt::Display for DdpDestination {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "DDP device {}", self.ddp_device)
+    }
+}
+
+impl Destinations {
+    // A universe mapped twice keeps the later mapping, and the conflict is noted
+    pub fn add(&mut self, port_address: u16, destination: Destination) {
+        let conflict = match destination {
+            Destination::Kinet(destination) => add_destination(&mut self.kinet, port_address, destination),
+            Destination::Opc(destination) => add_destination(&mut self.opc, port_address, destination),
+            Destination::Sacn(destination) => add_destination(&mut self.sacn, port_address, destination),
+            Destination::Artnet(destination) => add_destination(&mut self.artnet, port_address, destination),
+            Destination::Ddp(destination) => add_destination(&mut self.ddp, port_address, destination),
+        };
+        self.conflicts.extend(conflict);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.kinet.is_empty() && self.opc.is_empty() && self.sacn.is_empty() && self.artnet.is_empty() && self.ddp.is_empty()
+    }
+
+    // Every destination in order of universe, as reported by the HTTP API
+    pub fn summaries(&self) -> Vec<DestinationSummary> {
+        let mut summaries = vec!();
+        let mut add = |port_address: u16, protocol, name, address| {
+            summaries.push(DestinationSummary { port_address, protocol, name, address });
+        };
+        for (&port_address, destination) in &self.kinet {
+            add(port_address, "KiNET", format!("KiNET {}", destination), destination.kinet_socket_addr.to_string());
+        }
+        for (&port_address, destination) in &self.opc {
+            add(port_address, "OPC", destination.to_string(), destination.opc_address.clone());
+        }
+        for (&port_address, destination) in &self.sacn {
+            add(port_address, "sACN", destination.to_string(), destination.sacn_socket_addr.to_string());
+        }
+        for (&port_address, destination) in &self.artnet {
+            add(port_address, "Art-Net", destination.to_string(), destination.artnet_socket_addr.to_string());
+        }
+        for (&port_address, destination) in &self.ddp {
+            add(port_address, "DDP", destination.to_string(), destination.ddp_socket_addr.to_string());
+        }
+        summaries.sort_by_key(|summary| summary.port_address);
+        summaries
+    }
+}
+
+impl fmt::Display for ValidationIssue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.severity {
+            Severity::Warning => write!(f, "warning: {}", self.message),
+            Severity::Error => write!(f, "error: {}", self.message),
+        }
+    }
+}
+
+impl Configuration {
+    pub fn get_log_level(&self) -> Option<Level> {
+        match self.verbosity {
+            std::i8::MIN..=-1 => None,
+            0 => Some(log::Level::Error),
+            1 => Some(log::Level::Warn),
+            2 => Some(log::Level::Info),
+            3 => Some(log::Level::Debug),
+            4..=std::i8::MAX => Some(log::Level::Trace),
+        }
+    }
+
+    // Check for problems that don't prevent the configuration from loading, but will stop the bridge
+    // from working as intended: bind addresses that don't belong to this machine, several universes
+    // fighting over the same KiNET output, and destinations that can't be reached directly
+    pub fn validate(&self) -> Vec<ValidationIssue> {
+        self.validate_with(&utils::local_interfaces())
+    }
+
+    // Checks against the given network interfaces rather than this machine's, so tests give the
+    // same answer wherever they run
+    fn validate_with(&self, interfaces: &[Ifv4Addr]) -> Vec<ValidationIssue> {
+        let local_interface = |address: Ipv4Addr| interfaces.iter().find(|interface| interface.ip == address);
+        let mut issues = vec!();
+
+        for conflict in &self.destinations.conflicts {
+            issues.push(ValidationIssue { severity: Severity::Error, message: conflict.clone() });
+        }
+
+        let mut bind_addresses = vec!(
+            ("Art-Net receive", &self.artnet_receive_ip, self.artnet_receive_addr),
+            ("KiNET send", &self.kinet_send_ip, self.kinet_send_addr),
+        );
+        if self.http_port.is_some() && self.http_addr != self.artnet_receive_addr {
+            bind_addresses.push(("HTTP", &self.http_ip, self.http_addr));
+        }
+        for (name, given, address) in bind_addresses {
+            if !address.is_unspecified() && local_interface(address).is_none() {
+                issues.push(ValidationIssue {
+                    severity: Severity::Error,
+                    message: format!("{} address {} is not assigned to any local network interface", name, given),
+                });
+            }
+        }
+
+        let mut destinations: Vec<&KinetDestination> = self.destinations.kinet.values().collect();
+        destinations.sort_by_key(|destination| (destination.kinet_socket_addr, destination.kinet_port, destination.artnet_address()));
+
+        let mut unresolved: Vec<String> = self.destinations.kinet.values()
+            .filter(|destination| !is_resolved(destination.kinet_socket_addr))
+            .map(|destination| format!("KiNET destination {}", destination.kinet_address))
+            .chain(self.destinations.sacn.values()
+                .filter(|destination| !is_resolved(destination.sacn_socket_addr))
+                .map(|destination| format!("sACN destination {}", destination.sacn_unicast.as_deref().unwrap_or_default())))
+            .chain(self.destinations.artnet.values()
+                .filter(|destination| !is_resolved(destination.artnet_socket_addr))
+                .map(|destination| format!("Art-Net node {}", destination.artnet_node)))
+            .chain(self.destinations.ddp.values()
+                .filter(|destination| !is_resolved(destination.ddp_socket_addr))
+                .map(|destination| format!("DDP device {}", destination.ddp_device)))
+            .collect();
+        unresolved.sort();
+        unresolved.dedup();
+        for destination in unresolved {
+            issues.push(ValidationIssue {
+                severity: Severity::Warning,
+                message: format!("{} could not be resolved, so nothing will be sent to it until it can be", destination),
+            });
+        }
+
+        for shared in destinations.windows(2) {
+            if shared[0].kinet_socket_addr == shared[1].kinet_socket_addr && shared[0].kinet_port == shared[1].kinet_port
+                && is_resolved(shared[0].kinet_socket_addr) {
+                issues.push(ValidationIssue {
+                    severity: Severity::Warning,
+                    message: format!("Art-Net universes {} and {} both map to KiNET destination {} port {}",
+                        shared[0].artnet_address(), shared[1].artnet_address(), shared[0].kinet_address, shared[0].kinet_port),
+                });
+            }
+        }
+
+        if let Some(interface) = local_interface(self.kinet_send_addr) {
+            let netmask = u32::from(interface.netmask);
+            for destination in destinations {
+                if let SocketAddr::V4(address) = destination.kinet_socket_addr {
+                    if is_resolved(destination.kinet_socket_addr) && u32::from(*address.ip()) & netmask != u32::from(interface.ip) & netmask {
+                        issues.push(ValidationIssue {
+                            severity: Severity::Warning,
+                            message: format!("KiNET destination {} is not on the same subnet as KiNET send address {}, and can only be reached through a router",
+                                destination.kinet_address, self.kinet_send_ip),
+                        });
+                    }
+                }
+            }
+        }
+
+        let mut sacn_destinations: Vec<&SacnDestination> = self.destinations.sacn.values().collect();
+        sacn_destinations.sort_by_key(|destination| (destination.sacn_universe, destination.sacn_socket_addr, destination.port_address));
+
+        for shared in sacn_destinations.windows(2) {
+            if shared[0].sacn_universe == shared[1].sacn_universe && shared[0].sacn_socket_addr == shared[1].sacn_socket_addr
+                && is_resolved(shared[0].sacn_socket_addr) {
+                issues.push(ValidationIssue {
+                    severity: Severity::Warning,
+                    message: format!("Art-Net universes {} and {} both map to {}",
+                        shared[0].artnet_address(), shared[1].artnet_address(), shared[0]),
+                });
+            }
+        }
+
+        let mut artnet_destinations: Vec<&ArtnetDestination> = self.destinations.artnet.values().collect();
+        artnet_destinations.sort_by_key(|destination| (destination.artnet_socket_addr, destination.artnet_output_universe.value(), destination.port_address));
+
+        for shared in artnet_destinations.windows(2) {
+            if shared[0].artnet_socket_addr == shared[1].artnet_socket_addr && shared[0].artnet_output_universe == shared[1].artnet_output_universe
+                && is_resolved(shared[0].artnet_socket_addr) {
+                issues.push(ValidationIssue {
+                    severity: Severity::Warning,
+                    message: format!("Art-Net universes {} and {} both map to {}",
+                        shared[0].artnet_address(), shared[1].artnet_address(), shared[0]),
+                });
+            }
+        }
+
+        // Relaying to ourselves on a universe we route would send every packet round forever
+        for destination in &artnet_destinations {
+            if let SocketAddr::V4(address) = destination.artnet_socket_addr {
+                let ours = address.ip().is_loopback() || local_interface(*address.ip()).is_some();
+                if ours && (self.artnet_receive_addr.is_unspecified() || self.artnet_receive_addr == *address.ip())
+                    && self.destinations.artnet.contains_key(&destination.artnet_output_universe.value()) {
+                    issues.push(ValidationIssue {
+                        severity: Severity::Error,
+                        message: format!("Art-Net universe {} maps to {}, which is this bridge, and would be relayed again in a loop",
+                            destination.artnet_address(), destination),
+                    });
+                }
+            }
+        }
+
+        let mut opc_destinations: Vec<&OpcDestination> = self.destinations.opc.values().collect();
+        opc_destinations.sort_by_key(|destination| (&destination.opc_address, destination.opc_channel, destination.pixel_offset));
+
+        for shared in opc_destinations.windows(2) {
+            if shared[0].opc_address == shared[1].opc_address && shared[0].opc_channel == shared[1].opc_channel
+                && shared[0].pixel_range().end > shared[1].pixel_range().start {
+                issues.push(ValidationIssue {
+                    severity: Severity::Warning,
+                    message: format!("Art-Net universes {} and {} both write to pixels {}-{} on {}",
+                        shared[0].artnet_address(), shared[1].artnet_address(), shared[1].pixel_range().start,
+                        shared[0].pixel_range().end.min(shared[1].pixel_range().end) - 1, shared[0]),
+                });
+            }
+        }
+
+        let mut ddp_destinations: Vec<&DdpDestination> = self.destinations.ddp.values().collect();
+        ddp_destinations.sort_by_key(|destination| (destination.ddp_socket_addr, destination.pixel_offset));
+
+        for shared in ddp_destinations.windows(2) {
+            if shared[0].ddp_socket_addr == shared[1].ddp_socket_addr && shared[0].pixel_range().end > shared[1].pixel_range().start
+                && is_resolved(shared[0].ddp_socket_addr) {
+                issues.push(ValidationIssue {
+                    severity: Severity::Warning,
+                    message: format!("Art-Net universes {} and {} both write to pixels {}-{} on {}",
+                        shared[0].artnet_address(), shared[1].artnet_address(), shared[1].pixel_range().start,
+                        shared[0].pixel_range().end.min(shared[1].pixel_range().end) - 1, shared[0]),
+                });
+            }
+        }
+
+        issues
+    }
+
+    // Combine the command line arguments with the config file they name, if any
+    pub fn load(cli_config: &UserConfiguration) -> Result<Self, Error> {
+        let env_config = UserConfiguration::from_env()?;
+        let mut file_config = UserConfiguration::default();
+        if let Some(file_path) = cli_config.config_file.as_ref().or(env_config.config_file.as_ref()) {
+            file_config = UserConfiguration::from_file(file_path)?;
+        }
+        Configuration::from_user_configs(cli_config.clone(), env_config, file_config)
+    }
+
+    // The mappings listed in a JSON config file, as written there
+    pub fn read_file_mappings(cli_config: &UserConfiguration) -> Result<(String, serde_json::Value), Error> {
+        let path = editable_config_file(cli_config)?;
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Could not read configuration file {}: {}", path, e))?;
+        let file: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Could not parse configuration file {}: {}", path, e))?;
+        let mappings = file.get("mappings").cloned().unwrap_or_else(|| serde_json::Value::Array(vec!()));
+        Ok((path, mappings))
+    }
+
+    // Replaces the mappings in a JSON config file, keeping everything else in it as it was written. The
+    // new file is only written if it loads without errors, and replaces the old one in a single step, so
+    // the reloader never sees it half written. Callers saving from more than one thread need to take
+    // turns, as the file is read and then written.
+    pub fn write_file_mappings(cli_config: &UserConfiguration, mappings: serde_json::Value) -> Result<(Self, Vec<ValidationIssue>), Error> {
+        let path = editable_config_file(cli_config)?;
+        let contents = fs::read_to_string(&path)
+            .map_err(|e| anyhow!("Could not read configuration file {}: {}", path, e))?;
+        let file: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|e| anyhow!("Could not parse configuration file {}: {}", path, e))?;
+        let file = match file.as_object() {
+            Some(file) => file,
+            None => return Err(anyhow!("Configuration file {} doesn't contain an object", path)),
+        };
+
+        let contents = match top_level_value(&contents, "mappings") {
+            Some(range) => {
+                let line = contents[..range.start].rsplit('\n').next().unwrap_or_default();
+                let indent = &line[..line.len() - line.trim_start().len()];
+                format!("{}{}{}", &contents[..range.start], format_mappings(&mappings, indent), &contents[range.end..])
+            },
+            None => {
+                let end = contents.rfind('}').ok_or_else(|| anyhow!("Configuration file {} doesn't contain an object", path))?;
+                let separator = if file.is_empty() { "" } else { "," };
+                format!("{}{}\n    \"mappings\": {}\n{}", contents[..end].trim_end(), separator,
+                    format_mappings(&mappings, "    "), &contents[end..])
+            },
+        };
+        let file_config = UserConfiguration::parse(&contents, ConfigFormat::Json)?;
+        let cfg = Configuration::from_user_configs(cli_config.clone(), UserConfiguration::from_env()?, file_config)?;
+        let issues = cfg.validate();
+        let errors: Vec<String> = issues.iter()
+            .filter(|issue| issue.severity == Severity::Error)
+            .map(|issue| issue.message.clone())
+            .collect();
+        if !errors.is_empty() {
+            return Err(anyhow!("{}", errors.join("; ")));
+        }
+
+        let temporary_path = format!("{}.{}.tmp", path, std::process::id());
+        fs::write(&temporary_path, contents)
+            .and_then(|_| fs::rename(&temporary_path, &path))
+            .map_err(|e| anyhow!("Could not write configuration file {}: {}", path, e))?;
+        Ok((cfg, issues))
+    }
+
+    pub fn from_user_configs(cli_config: UserConfiguration, env_config: UserConfiguration, file_config: UserConfiguration) -> Result<Self, Error> {
+        // Return a configuration object we can use from the CLI, environment and optional config file.
+        // Single values are taken from the CLI first, then the environment, then the file.
+
+        let artnet_address = match cli_config.artnet_receive_ip
+            .or(env_config.artnet_receive_ip)
+            .or(file_config.artnet_receive_ip) {
+            None => return Err(anyhow!("No Art-Net listening address specified.")),
+            Some(addr) => addr,
+        };
+
+        let kinet_address = match cli_config.kinet_send_ip
+            .or(env_config.kinet_send_ip)
+            .or(file_config.kinet_send_ip) {
+            None => return Err(anyhow!("No KiNET output address specified.")),
+            Some(addr) => addr,
+        };
+
+        let mut supplies = file_config.supplies;
+        supplies.extend(env_config.supplies);
+        supplies.extend(cli_config.supplies);
+        let mut fixtures = file_config.fixtures;
+        fixtures.extend(env_config.fixtures);
+        fixtures.extend(cli_config.fixtures);
+        let mut opc_servers = file_config.opc_servers;
+        opc_servers.extend(env_config.opc_servers);
+        opc_servers.extend(cli_config.opc_servers);
+
+        // Mappings from every source are combined. Like supplies, they're added from the file first,
+        // so a universe also mapped in the environment or on the command line ends up mapped there.
+        let mut destinations = Destinations::default();
+        for mapping in file_config.mappings.unwrap_or_default().into_iter()
+            .chain(env_config.mappings.unwrap_or_default())
+            .chain(cli_config.mappings.unwrap_or_default()) {
+            for (port_address, destination) in mapping.to_destinations(&supplies, &fixtures, &opc_servers)? {
+                destinations.add(port_address, destination);
+            }
+        }
+        
+        let mut kinet_inputs: Vec<KinetSource> = vec!();
+        for input in cli_config.kinet_inputs.iter()
+            .chain(env_config.kinet_inputs.iter())
+            .chain(file_config.kinet_inputs.iter()) {
+            let source = input.to_kinet_source()?;
+            if kinet_inputs.iter().any(|other| other.kinet_source == source.kinet_source && other.kinet_port == source.kinet_port) {
+                return Err(anyhow!("KiNET input {} is configured more than once", source));
+            }
+            kinet_inputs.push(source);
+        }
+
+        if destinations.is_empty() && kinet_inputs.is_empty() {
+            return Err(anyhow!("No destination mappings specified."));
+        }
+
+        let default_verbosity: i8 = 2;
+        let verbosity = [cli_config.verbose, env_config.verbose, file_config.verbose].iter()
+            .fold(default_verbosity, |verbosity, verbose| verbosity.saturating_add(*verbose));
+        let verbosity = [cli_config.quiet, env_config.quiet, file_config.quiet].iter()
+            .fold(verbosity, |verbosity, quiet| verbosity.saturating_sub(*quiet));
+      
+        let kinet_flags = cli_config.kinet_flags
+            .or(env_config.kinet_flags)
+            .or(file_config.kinet_flags)
+            .unwrap_or(0);
+        let kinet_timer = cli_config.kinet_timer
+            .or(env_config.kinet_timer)
+            .or(file_config.kinet_timer)
+            .unwrap_or(-1);
+
+        let sacn_source_name = cli_config.sacn_source_name
+            .or(env_config.sacn_source_name)
+            .or(file_config.sacn_source_name)
+            .unwrap_or_else(|| DEFAULT_SACN_SOURCE_NAME.to_string());
+        if sacn_source_name.len() > sacn::MAX_SOURCE_NAME_LENGTH {
+            return Err(anyhow!("sACN source name {} is longer than {} bytes", sacn_source_name, sacn::MAX_SOURCE_NAME_LENGTH));
+        }
+        let sacn_cid = match cli_config.sacn_cid
+            .or(env_config.sacn_cid)
+            .or(file_config.sacn_cid) {
+            Some(cid) => utils::parse_uuid(&cid)?,
+            None => sacn::default_cid(&sacn_source_name),
+        };
+        let sacn_priority = cli_config.sacn_priority
+            .or(env_config.sacn_priority)
+            .or(file_config.sacn_priority)
+            .unwrap_or(sacn::DEFAULT_PRIORITY);
+        if sacn_priority > sacn::MAX_PRIORITY {
+            return Err(anyhow!("sACN priority {} is out of range (must be 0-{})", sacn_priority, sacn::MAX_PRIORITY));
+        }
+
+        let artnet_receive_addr = utils::resolve_bind_address(&artnet_address)?;
+        let kinet_send_addr = utils::resolve_bind_address(&kinet_address)?;
+
+        let osc_port = cli_config.osc_port
+            .or(env_config.osc_port)
+            .or(file_config.osc_port);
+        let http_port = cli_config.http_port
+            .or(env_config.http_port)
+            .or(file_config.http_port);
+        let http_ip = cli_config.http_ip
+            .or(env_config.http_ip)
+            .or(file_config.http_ip)
+            .unwrap_or_else(|| artnet_address.clone());
+        let http_addr = utils::resolve_bind_address(&http_ip)?;
+
+        let mut scene_definitions = file_config.scenes;
+        scene_definitions.extend(env_config.scenes);
+        scene_definitions.extend(cli_config.scenes);
+        let mut scenes = HashMap::new();
+        for (name, definition) in scene_definitions {
+            let scene = definition.to_scene().map_err(|e| anyhow!("Scene {}: {}", name, e))?;
+            scenes.insert(name, scene);
+        }
+
+        let config = Configuration {
+            artnet_receive_ip: artnet_address,
+            artnet_receive_addr,
+            kinet_send_ip: kinet_address,
+            kinet_send_addr,
+            destinations,
+            kinet_inputs,
+            kinet_flags,
+            kinet_timer,
+            sacn_source_name,
+            sacn_cid,
+            sacn_priority,
+            osc_port,
+            http_port,
+            http_ip,
+            http_addr,
+            scenes,
+            verbosity: verbosity,
+        };
+
+        return Ok(config);
+    }
+}
+
+// Deserialized by hand rather than with #[serde(untagged)], so that mistakes inside a mapping object
+// are reported as they are, with their location, instead of as a failure to match either form
+impl<'de> Deserialize<'de> for Mapping {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MappingVisitor;
+
+        impl<'de> Visitor<'de> for MappingVisitor {
+            type Value = Mapping;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("a map-string or a mapping object")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Mapping, E> {
+                Ok(Mapping::MapString(value.to_string()))
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Mapping, A::Error> {
+                NamedMapping::deserialize(de::value::MapAccessDeserializer::new(map)).map(Mapping::Named)
+            }
+        }
+
+        deserializer.deserialize_any(MappingVisitor)
+    }
+}
+
+impl From<&str> for Mapping {
+    fn from(map_string: &str) -> Self {
+        Mapping::MapString(map_string.to_string())
+    }
+}
+
+impl Mapping {
+    // Most mappings route one universe, but a DDP mapping can cover a run of them
+    pub fn to_destinations(&self, supplies: &HashMap<String, Supply>, fixtures: &HashMap<String, Fixture>, opc_servers: &HashMap<String, OpcServer>) -> Result<Vec<(u16, Destination)>> {
+        let mapping = match self {
+            Mapping::MapString(map_string) => return parse_map_string(map_string)
+                .map(|(port_address, destination)| vec!((port_address, Destination::Kinet(destination)))),
+            Mapping::Named(mapping) => mapping,
+        };
+
+        // The options a mapping sets decide what kind of destination it is. Pixel options are shared
+        // by the pixel protocols.
+        let single = match (mapping.has_opc_options(), mapping.has_sacn_options(), mapping.has_artnet_options(), mapping.has_ddp_options()) {
+            (false, false, false, false) if !mapping.has_pixel_options() => mapping.to_kinet_destination(supplies, fixtures)
+                .map(|(port_address, destination)| (port_address, Destination::Kinet(destination))),
+            (true, false, false, false) if !mapping.has_kinet_options() => mapping.to_opc_destination(opc_servers)
+                .map(|(port_address, destination)| (port_address, Destination::Opc(destination))),
+            (false, true, false, false) if !mapping.has_kinet_options() && !mapping.has_pixel_options() => mapping.to_sacn_destination()
+                .map(|(port_address, destination)| (port_address, Destination::Sacn(destination))),
+            (false, false, true, false) if !mapping.has_kinet_options() && !mapping.has_pixel_options() => mapping.to_artnet_destination()
+                .map(|(port_address, destination)| (port_address, Destination::Artnet(destination))),
+            (false, false, false, true) if !mapping.has_kinet_options() => return mapping.to_ddp_destinations()
+                .map(|destinations| destinations.into_iter()
+                    .map(|(port_address, destination)| (port_address, Destination::Ddp(destination)))
+                    .collect()),
+            _ => Err(anyhow!("Mapping for universe {} mixes options for different kinds of destination", mapping.universe)),
+        };
+        single.map(|destination| vec!(destination))
+    }
+}
+
+impl NamedMapping {
+    fn has_kinet_options(&self) -> bool {
+        self.supply.is_some() || self.fixture.is_some() || self.port.is_some() || self.channels.is_some() || self.start_code.is_some()
+    }
+
+    fn has_opc_options(&self) -> bool {
+        self.opc_server.is_some() || self.opc_channel.is_some()
+    }
+
+    fn has_pixel_options(&self) -> bool {
+        self.pixel_offset.is_some() || self.pixels.is_some()
+    }
+
+    fn has_sacn_options(&self) -> bool {
+        self.sacn_universe.is_some() || self.sacn_unicast.is_some() || self.sacn_priority.is_some()
+    }
+
+    fn has_artnet_options(&self) -> bool {
+        self.artnet_node.is_some() || self.artnet_universe.is_some()
+    }
+
+    fn has_ddp_options(&self) -> bool {
+        self.ddp_device.is_some() || self.ddp_universes.is_some()
+    }
+
+    // Named KiNET mappings are expanded into the equivalent map-string, so both forms share the same
+    // parsing, then any options that a map-string can't express are applied to the result
+    fn to_kinet_destination(&self, supplies: &HashMap<String, Supply>, fixtures: &HashMap<String, Fixture>) -> Result<(u16, KinetDestination)> {
+        let (supply_name, port, fixture) = match (&self.supply, &self.fixture) {
+            (Some(supply), None) => (supply, self.port, None),
+            (None, Some(fixture_name)) => {
+                let fixture = match fixtures.get(fixture_name) {
+                    Some(fixture) => fixture,
+                    None => return Err(anyhow!("Mapping for universe {} refers to unknown fixture {}", self.universe, fixture_name)),
+                };
+                if self.port.is_some() {
+                    return Err(anyhow!("Mapping for universe {} sets a port, but fixture {} already has one", self.universe, fixture_name));
+                }
+                (&fixture.supply, Some(fixture.port), Some(fixture))
+            },
+            _ => return Err(anyhow!("Mapping for universe {} must refer to exactly one supply, fixture, OPC server, sACN universe, Art-Net node or DDP device", self.universe)),
+        };
+
+        let supply = match supplies.get(supply_name) {
+            Some(supply) => supply,
+            None => return Err(anyhow!("Mapping for universe {} refers to unknown supply {}", self.universe, supply_name)),
+        };
+
+        let port = match (supply.protocol, port) {
+            (Some(KinetProtocol::V1), None) | (Some(KinetProtocol::V1), Some(0)) => 0,
+            (Some(KinetProtocol::V1), Some(_)) => {
+                return Err(anyhow!("Supply {} uses KiNET v1, which doesn't have output ports", supply_name));
+            },
+            (Some(KinetProtocol::V2), None) | (Some(KinetProtocol::V2), Some(0)) => {
+                return Err(anyhow!("Mapping for universe {} must specify a port on KiNET v2 supply {}", self.universe, supply_name));
+            },
+            (_, port) => port.unwrap_or(0),
+        };
+        if port > supply.ports {
+            return Err(anyhow!("Port {} is out of range for supply {}, which has {} ports", port, supply_name, supply.ports));
+        }
+
+        let channels = self.channels.or(fixture.and_then(|fixture| fixture.channels));
+        if let Some(channels) = channels {
+            if channels == 0 || channels as usize > MAX_DMX_LENGTH {
+                return Err(anyhow!("Mapping for universe {} has {} channels (must be 1-{})", self.universe, channels, MAX_DMX_LENGTH));
+            }
+        }
+
+        let (port_address, mut destination) = parse_map_string(&format!("{}:{}:{}", self.universe, supply.ip, port))?;
+        destination.kinet_channels = channels;
+        destination.kinet_trim_dmx_out = supply.trim_dmx_out;
+
+        // KiNET v1 has nowhere to put a start code
+        destination.kinet_start_code = self.start_code.or(fixture.and_then(|fixture| fixture.start_code));
+        if destination.kinet_start_code.is_some() && destination.kinet_port == 0 {
+            return Err(anyhow!("Mapping for universe {} sets a start code, which needs a KiNET v2 port", self.universe));
+        }
+        Ok((port_address, destination))
+    }
+
+    fn to_opc_destination(&self, opc_servers: &HashMap<String, OpcServer>) -> Result<(u16, OpcDestination)> {
+        let server_name = match &self.opc_server {
+            Some(server_name) => server_name,
+            None => return Err(anyhow!("Mapping for universe {} sets OPC options, but doesn't refer to an OPC server", self.universe)),
+        };
+
+        let server = match opc_servers.get(server_name) {
+            Some(server) => server,
+            None => return Err(anyhow!("Mapping for universe {} refers to unknown OPC server {}", self.universe, server_name)),
+        };
+
+        // OPC messages have a 16-bit length, which limits how far along a channel pixels can be
+        if let Some(pixels) = self.pixels {
+            if pixels == 0 || pixels > opc::MAX_PIXELS {
+                return Err(anyhow!("Mapping for universe {} has {} pixels (must be 1-{})", self.universe, pixels, opc::MAX_PIXELS));
+            }
+        }
+        let pixel_offset = self.pixel_offset.unwrap_or(0);
+        if (pixel_offset as usize + self.pixels.unwrap_or(opc::MAX_PIXELS) as usize) * 3 > u16::MAX as usize {
+            return Err(anyhow!("Pixel offset {} is too large for an OPC message", pixel_offset));
+        }
+
+        let port_address = self.universe.port_address()?;
+        let destination = OpcDestination {
+            port_address,
+            opc_address: server.socket_address()?,
+            opc_channel: self.opc_channel.unwrap_or(0),
+            pixel_offset,
+            pixels: self.pixels,
+        };
+        Ok((port_address.value(), destination))
+    }
+
+    // sACN goes to the universe's multicast group unless a unicast address is given
+    fn to_sacn_destination(&self) -> Result<(u16, SacnDestination)> {
+        let sacn_universe = match self.sacn_universe {
+            Some(sacn_universe) if (1..=sacn::MAX_UNIVERSE).contains(&sacn_universe) => sacn_universe,
+            Some(sacn_universe) => return Err(anyhow!("sACN universe {} is out of range (must be 1-{})", sacn_universe, sacn::MAX_UNIVERSE)),
+            None => return Err(anyhow!("Mapping for universe {} sets sACN options, but doesn't give an sACN universe", self.universe)),
+        };
+        if let Some(priority) = self.sacn_priority {
+            if priority > sacn::MAX_PRIORITY {
+                return Err(anyhow!("sACN priority {} is out of range (must be 0-{})", priority, sacn::MAX_PRIORITY));
+            }
+        }
+
+        let sacn_socket_addr = match &self.sacn_unicast {
+            Some(address) => resolve_or_defer(address, sacn::SACN_PORT),
+            None => SocketAddr::new(IpAddr::V4(sacn::multicast_address(sacn_universe)), sacn::SACN_PORT),
+        };
+
+        let port_address = self.universe.port_address()?;
+        let destination = SacnDestination {
+            port_address,
+            sacn_universe,
+            sacn_unicast: self.sacn_unicast.clone(),
+            sacn_socket_addr,
+            sacn_priority: self.sacn_priority,
+        };
+        Ok((port_address.value(), destination))
+    }
+
+    // Art-Net is relayed unicast to a single node, keeping the universe number unless told otherwise
+    fn to_artnet_destination(&self) -> Result<(u16, ArtnetDestination)> {
+        let artnet_node = match &self.artnet_node {
+            Some(artnet_node) => artnet_node,
+            None => return Err(anyhow!("Mapping for universe {} sets Art-Net options, but doesn't give an Art-Net node", self.universe)),
+        };
+
+        let port_address = self.universe.port_address()?;
+        let destination = ArtnetDestination {
+            port_address,
+            artnet_node: artnet_node.clone(),
+            artnet_socket_addr: resolve_or_defer(artnet_node, artnet::ARTNET_PORT),
+            artnet_output_universe: match &self.artnet_universe {
+                Some(universe) => universe.port_address()?,
+                None => port_address,
+            },
+        };
+        Ok((port_address.value(), destination))
+    }
+
+    // A DDP mapping covers ddp_universes consecutive Art-Net universes, laid end to end in the
+    // device's frame from pixel_offset, each taking the given number of pixels
+    fn to_ddp_destinations(&self) -> Result<Vec<(u16, DdpDestination)>> {
+        let ddp_device = match &self.ddp_device {
+            Some(ddp_device) => ddp_device,
+            None => return Err(anyhow!("Mapping for universe {} sets DDP options, but doesn't give a DDP device", self.universe)),
+        };
+        if let Some(pixels) = self.pixels {
+            if pixels == 0 || pixels > opc::MAX_PIXELS {
+                return Err(anyhow!("Mapping for universe {} has {} pixels (must be 1-{})", self.universe, pixels, opc::MAX_PIXELS));
+            }
+        }
+        let universes = self.ddp_universes.unwrap_or(1);
+        if universes == 0 {
+            return Err(anyhow!("Mapping for universe {} covers no DDP universes", self.universe));
+        }
+
+        let ddp_socket_addr = resolve_or_defer(ddp_device, ddp::DDP_PORT);
+        let first = self.universe.port_address()?;
+        let pixels = self.pixels.unwrap_or(opc::MAX_PIXELS) as u32;
+        (0..universes)
+            .map(|index| {
+                let port_address = PortAddress::from_flat(first.value().saturating_add(index))
+                    .map_err(|_| anyhow!("Mapping for universe {} runs past the last Art-Net universe", self.universe))?;
+                let destination = DdpDestination {
+                    port_address,
+                    ddp_device: ddp_device.clone(),
+                    ddp_socket_addr,
+                    pixel_offset: self.pixel_offset.unwrap_or(0) as u32 + index as u32 * pixels,
+                    pixels: self.pixels,
+                };
+                Ok((port_address.value(), destination))
+            })
+            .collect()
+    }
+}
+
+impl UniverseSpec {
+    pub fn port_address(&self) -> Result<PortAddress> {
+        match self {
+            UniverseSpec::Number(universe) => PortAddress::from_flat(*universe),
+            UniverseSpec::Text(universe) => parse_port_address(universe.split(':').collect(), universe),
+        }
+    }
+}
+
+impl KinetInput {
+    fn to_kinet_source(&self) -> Result<KinetSource> {
+        let kinet_source = match &self.source {
+            Some(source) => match Ipv4Addr::from_str(source) {
+                Ok(source) => Some(source),
+                Err(_) => return Err(anyhow!("KiNET input source {} is not an IPv4 address", source)),
+            },
+            None => None,
+        };
+
+        Ok(KinetSource {
+            kinet_source,
+            kinet_port: self.port,
+            port_address: self.universe.port_address()?,
+            artnet_node: self.artnet_node.clone(),
+            artnet_socket_addr: resolve_ipv4_address(&self.artnet_node, artnet::ARTNET_PORT)?,
+        })
+    }
+}
+
+impl KinetSource {
+    // A source address only matches packets from that controller; without one, any controller
+    pub fn matches(&self, source: IpAddr, port: u8) -> bool {
+        let from_source = match self.kinet_source {
+            Some(kinet_source) => IpAddr::V4(kinet_source) == source,
+            None => true,
+        };
+        self.kinet_port == port && from_source
+    }
+}
+
+impl fmt::Display for KinetSource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kinet_source {
+            Some(source) => write!(f, "KiNET port {} from {}", self.kinet_port, source),
+            None => write!(f, "KiNET port {}", self.kinet_port),
+        }
+    }
+}
+
+impl SceneDefinition {
+    fn to_scene(&self) -> Result<Scene> {
+        if let Some(master) = self.master {
+            if !(0.0..=1.0).contains(&master) {
+                return Err(anyhow!("Master level {} is out of range (must be 0-1)", master));
+            }
+        }
+
+        let mut levels = BTreeMap::new();
+        for (universe, universe_levels) in &self.levels {
+            if universe_levels.len() > MAX_DMX_LENGTH {
+                return Err(anyhow!("Universe {} has {} levels (must be up to {})", universe, universe_levels.len(), MAX_DMX_LENGTH));
+            }
+            let port_address = UniverseSpec::Text(universe.clone()).port_address()?;
+            levels.insert(port_address.value(), universe_levels.clone());
+        }
+        Ok(Scene { master: self.master, blackout: self.blackout, levels })
+    }
+}
+
+impl OpcServer {
+    // The port can be left off, in which case the standard OPC port is used
+    fn socket_address(&self) -> Result<String> {
+        match self.address.rfind(':') {
+            None => Ok(format!("{}:{}", self.address, opc::OPC_PORT)),
+            Some(separator) => match self.address[separator + 1..].parse::<u16>() {
+                Ok(_) if separator > 0 => Ok(self.address.clone()),
+                _ => Err(anyhow!("Could not understand {} as an OPC server address", self.address)),
+            },
+        }
+    }
 }
 
-impl Configuration {
-    pub fn get_log_level(&self) -> Option<Level> {
-        match self.verbosity {
-            std::i8::MIN..=-1 => None,
-            0 => Some(log::Level::Error),
-            1 => Some(log::Level::Warn),
-            2 => Some(log::Level::Info),
-            3 => Some(log::Level::Debug),
-            4..=std::i8::MAX => Some(log::Level::Trace),
+impl fmt::Display for UniverseSpec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UniverseSpec::Number(universe) => write!(f, "{}", universe),
+            UniverseSpec::Text(universe) => write!(f, "{}", universe),
         }
     }
+}
 
-    pub fn from_user_configs(cli_config: UserConfiguration, file_config: UserConfiguration) -> Result<Self, Error> {
-        // Return a configuration object we can use from both the CLI and optional config file.
+impl UserConfiguration {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<UserConfiguration, Error> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| anyhow!("Could not read configuration file {}: {}", path.display(), e))?;
+        UserConfiguration::parse(&contents, ConfigFormat::from_path(path))
+            .map_err(|e| anyhow!("Could not parse configuration file {}: {}", path.display(), e))
+    }
 
-         let artnet_address = match cli_config.artnet_receive_ip {
-            None => match file_config.artnet_receive_ip {
-                None => return Err(anyhow!("No Art-Net listening address specified.")),
-                Some(addr) => addr,
-            },
-            Some(addr) => addr,
-        };
+    pub fn from_env() -> Result<UserConfiguration, Error> {
+        UserConfiguration::from_env_vars(env::vars())
+    }
 
-        let kinet_address = match cli_config.kinet_send_ip {
-            None => match file_config.kinet_send_ip {
-                None => return Err(anyhow!("No KiNET output address specified.")),
-                Some(addr) => addr,
-            },
-            Some(addr) => addr,
-        };
+    // Read options from ARTNET_BRIDGE_* environment variables. Mappings are separated by commas or
+    // whitespace. Unknown ARTNET_BRIDGE_* variables are rejected so that typos don't go unnoticed.
+    pub fn from_env_vars<I: IntoIterator<Item = (String, String)>>(vars: I) -> Result<UserConfiguration, Error> {
+        let mut cfg = UserConfiguration::default();
 
-        let mut mappings = vec!();
-        mappings.extend(cli_config.mappings.unwrap_or_default());
-        mappings.extend(file_config.mappings.unwrap_or_default());
-        
-        if mappings.len() == 0 {
-            return Err(anyhow!("No KiNET destination mappings specified."));
+        for (name, value) in vars {
+            let option = match name.strip_prefix(ENV_PREFIX) {
+                Some(option) => option,
+                None => continue,
+            };
+            // -vvvv and -qqq are as far as the levels go
+            let count = || value.trim().parse::<i8>().ok()
+                .filter(|count| (0..=4).contains(count))
+                .ok_or_else(|| anyhow!("Could not understand {} as a value for {}", value, name));
+            let not_understood = |_| anyhow!("Could not understand {} as a value for {}", value, name);
+
+            match option {
+                "ARTNET_RECEIVE_IP" => cfg.artnet_receive_ip = Some(value.trim().to_string()),
+                "KINET_SEND_IP" => cfg.kinet_send_ip = Some(value.trim().to_string()),
+                "MAPPINGS" => {
+                    cfg.mappings = Some(value
+                        .split(|c: char| c == ',' || c.is_whitespace())
+                        .filter(|mapping| !mapping.is_empty())
+                        .map(Mapping::from)
+                        .collect());
+                },
+                "KINET_FLAGS" => cfg.kinet_flags = Some(utils::parse_u16(value.trim()).map_err(not_understood)?),
+                "KINET_TIMER" => cfg.kinet_timer = Some(value.trim().parse::<i32>().map_err(not_understood)?),
+                "SACN_SOURCE_NAME" => cfg.sacn_source_name = Some(value.clone()),
+                "SACN_CID" => cfg.sacn_cid = Some(value.trim().to_string()),
+                "SACN_PRIORITY" => cfg.sacn_priority = Some(value.trim().parse::<u8>().map_err(not_understood)?),
+                "OSC_PORT" => cfg.osc_port = Some(value.trim().parse::<u16>().map_err(not_understood)?),
+                "HTTP_PORT" => cfg.http_port = Some(value.trim().parse::<u16>().map_err(not_understood)?),
+                "HTTP_IP" => cfg.http_ip = Some(value.trim().to_string()),
+                "CONFIG_FILE" => cfg.config_file = Some(value.trim().to_string()),
+                "VERBOSE" => cfg.verbose = count()?,
+                "QUIET" => cfg.quiet = count()?,
+                _ => return Err(anyhow!("Unknown environment variable {}", name)),
+            }
         }
 
-        mappings.sort_unstable();
-        mappings.dedup();
+        Ok(cfg)
+    }
 
-        let default_verbosity: i8 = 2;
-        let verbosity = default_verbosity 
-            + cli_config.verbose - cli_config.quiet
-            + file_config.verbose - file_config.quiet;
-      
-        let config = Configuration {
-            artnet_receive_ip: artnet_address,
-            kinet_send_ip: kinet_address,
-            kinet_destinations: mappings_to_destinations(mappings)?,
-            verbosity: verbosity,
+    // Each format's errors already carry the line and column of the offending entry
+    pub fn parse(contents: &str, format: ConfigFormat) -> Result<UserConfiguration, Error> {
+        let cfg = match format {
+            ConfigFormat::Json => serde_json::from_str(contents)?,
+            ConfigFormat::Toml => toml::from_str(contents)?,
+            ConfigFormat::Yaml => serde_yaml::from_str(contents)?,
         };
+        Ok(cfg)
+    }
+}
 
-        return Ok(config);
+impl ConfigFormat {
+    pub fn from_path(path: &Path) -> Self {
+        let extension = path.extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension.as_deref() {
+            Some("toml") => ConfigFormat::Toml,
+            Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+            _ => ConfigFormat::Json,
+        }
     }
 }
 
-impl UserConfiguration {
-    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<UserConfiguration, Error> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let cfg = serde_json::from_reader(reader)?;
-        Ok(cfg)
+// Only JSON config files can be written back, as the other formats would lose their comments
+fn editable_config_file(cli_config: &UserConfiguration) -> Result<String, Error> {
+    let path = match cli_config.config_file.clone().or(UserConfiguration::from_env()?.config_file) {
+        Some(path) => path,
+        None => return Err(anyhow!("No configuration file was given to save mappings to")),
+    };
+    match ConfigFormat::from_path(Path::new(&path)) {
+        ConfigFormat::Json => Ok(path),
+        _ => Err(anyhow!("Configuration file {} isn't JSON, so it can't be edited here", path)),
+    }
+}
+
+// Where the value of a key in a JSON object's outermost level starts and ends in its text, so it can
+// be replaced without disturbing the rest of the file
+fn top_level_value(contents: &str, key: &str) -> Option<Range<usize>> {
+    let bytes = contents.as_bytes();
+    let skip_whitespace = |index: usize| index + contents[index..].len() - contents[index..].trim_start().len();
+    let mut depth = 0;
+    let mut start = None;
+    let mut index = 0;
+    while index < bytes.len() {
+        match bytes[index] {
+            b'"' => {
+                let mut end = index + 1;
+                while end < bytes.len() && bytes[end] != b'"' {
+                    end += if bytes[end] == b'\\' { 2 } else { 1 };
+                }
+                let colon = skip_whitespace((end + 1).min(bytes.len()));
+                if depth == 1 && start.is_none() && bytes.get(colon) == Some(&b':')
+                    && serde_json::from_str::<String>(contents.get(index..=end)?).ok()? == key {
+                    start = Some(skip_whitespace(colon + 1));
+                }
+                index = end;
+            },
+            b'{' | b'[' => depth += 1,
+            b'}' | b']' if depth == 1 => return start.map(|start| start..contents[..index].trim_end().len()),
+            b'}' | b']' => depth -= 1,
+            b',' if depth == 1 && start.is_some() => return start.map(|start| start..contents[..index].trim_end().len()),
+            _ => {},
+        }
+        index += 1;
     }
+    None
 }
 
-fn mappings_to_destinations(mappings: Vec<String>) -> Result<HashMap<u16, KinetDestination>> {
-    let mut destination_map = HashMap::new();
+// One mapping to a line, indented to match the line the key is on
+fn format_mappings(mappings: &serde_json::Value, indent: &str) -> String {
+    match mappings.as_array() {
+        Some(mappings) if !mappings.is_empty() => {
+            let lines: Vec<String> = mappings.iter().map(|mapping| format!("{}    {}", indent, mapping)).collect();
+            format!("[\n{}\n{}]", lines.join(",\n"), indent)
+        },
+        _ => mappings.to_string(),
+    }
+}
 
-    for dest in mappings {
-        let mut tokens: Vec<&str> = dest.split(':').collect();
-        let kinet_address: String;
-        let kinet_port: u8;
+// A KiNET destination can be a literal IPv4 address or a hostname (including names from /etc/hosts or
+// mDNS .local names, if the system resolver supports them.) Dotted numbers that aren't a valid IPv4
+// address are rejected rather than being handed to the resolver.
+fn is_kinet_address(item: &str) -> bool {
+    if Ipv4Addr::from_str(item).is_ok() {
+        return true;
+    }
 
-        let item = tokens.pop().unwrap_or_default();
-        match Ipv4Addr::from_str(item) {
-            Ok(_) => {
-                kinet_address = item.to_string();
-                kinet_port = 0;
-            },
-            Err(_) => {
-                kinet_port = match item.parse::<u8>() {
-                    Ok(port) => {
-                        if port > 16 {
-                            return Err(anyhow!("KiNET destination port too large (must be 0 for KiNET V1, or 1-16 for V2)"));
-                        }
-                        port
-                    }
-                    Err(_) => {
-                        return Err(anyhow!("Could not understand {} as a KiNET destination address or port", item));
-                    }
-                };
-                let item = tokens.pop().unwrap_or_default();
-                match Ipv4Addr::from_str(item) {
-                    Ok(_) => {
-                        kinet_address = item.to_string();
-                    },
-                    Err(_) => {
-                        return Err(anyhow!("Could not understand {} as a KiNET destination address", item));
-                    }
-                }
+    let valid_label = |label: &str| {
+        !label.is_empty() && label.len() <= 63
+            && !label.starts_with('-') && !label.ends_with('-')
+            && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+    };
+
+    item.len() <= 253
+        && item.chars().any(|c| c.is_ascii_alphabetic())
+        && item.trim_end_matches('.').split('.').all(valid_label)
+}
+
+// A destination given by name rather than IPv4 address, which the resolver looks up again from time to time
+pub fn is_hostname(address: &str) -> bool {
+    Ipv4Addr::from_str(address).is_err()
+}
+
+// A hostname that can't be resolved yet, such as a supply still waiting for DHCP, is held with an
+// unspecified address until the resolver fills it in. Nothing is sent to it in the meantime.
+fn resolve_or_defer(address: &str, port: u16) -> SocketAddr {
+    resolve_ipv4_address(address, port).unwrap_or_else(|_| unresolved_address(port))
+}
+
+pub fn unresolved_address(port: u16) -> SocketAddr {
+    SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), port)
+}
+
+pub fn is_resolved(address: SocketAddr) -> bool {
+    !address.ip().is_unspecified()
+}
+
+pub fn resolve_ipv4_address(address: &str, port: u16) -> Result<SocketAddr> {
+    match (address, port).to_socket_addrs() {
+        Ok(mut addresses) => {
+            // Output sockets are bound to an IPv4 address, so skip any IPv6 results
+            match addresses.find(|address| address.is_ipv4()) {
+                Some(address) => Ok(address),
+                None => Err(anyhow!("Could not create socket address for {}", address)),
             }
-        }
+        },
+        Err(_) => Err(anyhow!("Could not create socket address for {}", address)),
+    }
+}
 
-        let kinet_socket_addr = match (&kinet_address[..], 6038).to_socket_addrs() {
-            Ok(mut addresses) => {
-                match addresses.next() {
-                    Some(address) => { address },
-                    None => {
-                        return Err(anyhow!("Could not create socket address for {}", kinet_address));
-                    }
+fn parse_map_string(dest: &str) -> Result<(u16, KinetDestination)> {
+    let mut tokens: Vec<&str> = dest.split(':').collect();
+    let kinet_address: String;
+    let kinet_port: u8;
+
+    let item = tokens.pop().unwrap_or_default();
+    if is_kinet_address(item) {
+        kinet_address = item.to_string();
+        kinet_port = 0;
+    } else {
+        kinet_port = match item.parse::<u8>() {
+            Ok(port) => {
+                if port > 16 {
+                    return Err(anyhow!("KiNET destination port too large (must be 0 for KiNET V1, or 1-16 for V2)"));
                 }
-            },
+                port
+            }
             Err(_) => {
-                return Err(anyhow!("Could not create socket address for {}", kinet_address));
+                return Err(anyhow!("Could not understand {} as a KiNET destination address or port", item));
             }
         };
-        
+        let item = tokens.pop().unwrap_or_default();
+        if is_kinet_address(item) {
+            kinet_address = item.to_string();
+        } else {
+            return Err(anyhow!("Could not understand {} as a KiNET destination address", item));
+        }
+    }
+
+    let port_address = parse_port_address(tokens, dest)?;
+
+    let kinet_socket_addr = resolve_or_defer(&kinet_address, kinet::KINET_PORT);
+    let combined_address = port_address.value();
+
+    let destination = KinetDestination {
+        artnet_network: port_address.network() as u16,
+        artnet_subnet: port_address.subnet(),
+        artnet_universe: port_address.universe(),
+        kinet_address,
+        kinet_socket_addr,
+        kinet_port: kinet_port,
+        kinet_channels: None,
+        kinet_trim_dmx_out: false,
+        kinet_start_code: None,
+    };
+
+    Ok((combined_address, destination))
+}
+
+// A single Art-Net value is a flat 15-bit Port-Address (0-32767), which for 0-15 is the same
+// as that universe on network 0, subnet 0
+fn parse_port_address(mut tokens: Vec<&str>, dest: &str) -> Result<PortAddress> {
+    if tokens.len() == 1 {
+        let val = tokens.pop().unwrap_or_default();
+        match val.parse::<u16>() {
+            Ok(n) => PortAddress::from_flat(n),
+            _ => {
+                return Err(anyhow!("Could not understand {} as an Art-Net universe", val));
+            }
+        }
+    } else {
         let artnet_universe = match tokens.pop() {
             Some(val) => {
-                match val.parse::<u8>() {
+                match val.parse::<u16>() {
                     Ok(n) => n,
                     _ => {
                         return Err(anyhow!("Could not understand {} as an Art-Net universe", val));
@@ -193,7 +1486,7 @@ fn mappings_to_destinations(mappings: Vec<String>) -> Result<HashMap<u16, KinetD
         };
         let artnet_subnet = match tokens.pop() {
             Some(val) => {
-                match val.parse::<u8>() {
+                match val.parse::<u16>() {
                     Ok(n) => n,
                     _ => {
                         return Err(anyhow!("Could not understand {} as an Art-Net subnet", val));
@@ -217,23 +1510,23 @@ fn mappings_to_destinations(mappings: Vec<String>) -> Result<HashMap<u16, KinetD
         if tokens.len() != 0 {
             return Err(anyhow!("Too many values provided in mapping {}", dest));
         }
-        
-        let combined_address = 
-            (artnet_universe & 0x0F) as u16 + 
-            ((artnet_subnet & 0x0F) << 4) as u16 +
-            ((artnet_network & 0x7F) << 8);
-
-        destination_map.insert(combined_address, KinetDestination {
-            artnet_network,
-            artnet_subnet,
-            artnet_universe,
-            kinet_address,
-            kinet_socket_addr,
-            kinet_port: kinet_port,
-        });
+
+        PortAddress::new(artnet_network, artnet_subnet, artnet_universe)
     }
+}
 
-    return Ok(destination_map);
+// Two spellings of the same mapping are harmless, but a universe can only go to one place of each type
+// Returns a description of the conflict if the universe was already mapped somewhere else
+fn add_destination<T: PartialEq + fmt::Display>(destination_map: &mut HashMap<u16, T>, port_address: u16, destination: T) -> Option<String> {
+    let conflict = destination_map.get(&port_address)
+        .filter(|existing| **existing != destination)
+        .map(|existing| {
+            let universe = PortAddress::from(port_address);
+            format!("Art-Net universe {} ({}) is mapped to both {} and {}", universe, universe.value(), existing, destination)
+        });
+
+    destination_map.insert(port_address, destination);
+    conflict
 }
 
 
@@ -251,6 +1544,7 @@ mod tests {
                 0x0,
                 KinetDestination {
                     artnet_network: 0, artnet_subnet: 0, artnet_universe: 0, kinet_port: 0,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "10.0.0.1".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 6038)
                 },
@@ -260,6 +1554,7 @@ mod tests {
                 0x0,
                 KinetDestination {
                     artnet_network: 0, artnet_subnet: 0, artnet_universe: 0, kinet_port: 16,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "10.0.0.1".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 6038)
                 },
@@ -269,6 +1564,7 @@ mod tests {
                 0x216,
                 KinetDestination {
                     artnet_network: 2, artnet_subnet: 1, artnet_universe: 6, kinet_port: 4,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "192.168.0.1".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)), 6038)
                 },
@@ -278,6 +1574,7 @@ mod tests {
                 0x316,
                 KinetDestination {
                     artnet_network: 3, artnet_subnet: 1, artnet_universe: 6, kinet_port: 0,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "192.168.0.1".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 0, 1)), 6038)
                 },
@@ -287,6 +1584,7 @@ mod tests {
                 0x010,
                 KinetDestination {
                     artnet_network: 0, artnet_subnet: 1, artnet_universe: 0, kinet_port: 3,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "192.168.1.122".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 122)), 6038)
                 },
@@ -296,6 +1594,7 @@ mod tests {
                 0x015,
                 KinetDestination {
                     artnet_network: 0, artnet_subnet: 1, artnet_universe: 5, kinet_port: 0,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "192.168.1.122".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 122)), 6038)
                 },
@@ -305,6 +1604,7 @@ mod tests {
                 0x007,
                 KinetDestination {
                     artnet_network: 0, artnet_subnet: 0, artnet_universe: 7, kinet_port: 3,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "192.168.4.50".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 4, 50)), 6038)
                 },
@@ -314,10 +1614,52 @@ mod tests {
                 0x009,
                 KinetDestination {
                     artnet_network: 0, artnet_subnet: 0, artnet_universe: 9, kinet_port: 0,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+                    kinet_address: "192.168.4.50".to_string(),
+                    kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 4, 50)), 6038)
+                },
+            ),
+            (
+                "300:192.168.4.50:2",
+                0x12C,
+                KinetDestination {
+                    artnet_network: 1, artnet_subnet: 2, artnet_universe: 12, kinet_port: 2,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
                     kinet_address: "192.168.4.50".to_string(),
                     kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 4, 50)), 6038)
                 },
             ),
+            (
+                "32767:192.168.4.50",
+                0x7FFF,
+                KinetDestination {
+                    artnet_network: 127, artnet_subnet: 15, artnet_universe: 15, kinet_port: 0,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+                    kinet_address: "192.168.4.50".to_string(),
+                    kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 4, 50)), 6038)
+                },
+            ),
+            (
+                "2:localhost:5",
+                0x002,
+                KinetDestination {
+                    artnet_network: 0, artnet_subnet: 0, artnet_universe: 2, kinet_port: 5,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+                    kinet_address: "localhost".to_string(),
+                    kinet_socket_addr: SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 6038)
+                },
+            ),
+            (
+                // .invalid names never resolve, so this is kept until the resolver can fill it in
+                "3:pds.invalid:1",
+                0x003,
+                KinetDestination {
+                    artnet_network: 0, artnet_subnet: 0, artnet_universe: 3, kinet_port: 1,
+                    kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+                    kinet_address: "pds.invalid".to_string(),
+                    kinet_socket_addr: unresolved_address(6038)
+                },
+            ),
         );
 
         let bad_cases = vec!(
@@ -332,20 +1674,648 @@ mod tests {
             "-5:192.168.0.1", // not an unsigned integer
             "1:-2:1:192.168.0.1:0", // not an unsigned integer
             "-33:9:1:192.168.0.1:0", // not an unsigned integer
-            // TODO: validate and test that Art-Net network numbers are not out of range
+            "1:-pds-.example:0", // not a valid hostname
+            "1:pds_1:0", // not a valid hostname
+            "128:0:0:10.0.0.1", // Art-Net network out of range
+            "0:16:0:10.0.0.1", // Art-Net subnet out of range
+            "16:0:10.0.0.1", // Art-Net subnet out of range
+            "0:0:16:10.0.0.1", // Art-Net universe out of range
+            "32768:10.0.0.1:1", // Art-Net Port-Address out of range
         );
 
         for case in good_cases {
-            let dest = mappings_to_destinations(vec!(case.0.to_string())).unwrap();
-            let key = &(case.1 as u16);
-            assert!(dest.contains_key(key), "destination key not correct for {}, expected {:#x}, got {:#x}", case.0, key, dest.keys().next().unwrap());
-            assert_eq!(dest.len(), 1, "too many destinations created for {}", case.0);
-            assert_eq!(dest[key], case.2, "destination did not match for {}", case.0);
+            let (key, dest) = parse_map_string(case.0).unwrap();
+            assert_eq!(key, case.1 as u16, "destination key not correct for {}, expected {:#x}, got {:#x}", case.0, case.1, key);
+            assert_eq!(dest, case.2, "destination did not match for {}", case.0);
+        }
+
+        for case in bad_cases {
+            parse_map_string(case).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+        }
+
+        // The same mapping written two ways is fine, the same universe mapped to two places isn't
+        let mut dest = HashMap::new();
+        for mapping in &["1:10.0.0.1", "0:0:1:10.0.0.1:0"] {
+            let (key, destination) = parse_map_string(mapping).unwrap();
+            assert_eq!(add_destination(&mut dest, key, destination), None);
+        }
+        assert_eq!(dest.len(), 1);
+        let (key, destination) = parse_map_string("1:10.0.0.2").unwrap();
+        assert_eq!(add_destination(&mut dest, key, destination),
+            Some("Art-Net universe 0:0:1 (1) is mapped to both 10.0.0.1 port 0 and 10.0.0.2 port 0".to_string()));
+        assert_eq!(dest[&1].kinet_address, "10.0.0.2");
+    }
+
+    // A machine with only a loopback interface
+    fn interfaces() -> Vec<Ifv4Addr> {
+        vec!(Ifv4Addr { ip: Ipv4Addr::LOCALHOST, netmask: Ipv4Addr::new(255, 0, 0, 0), broadcast: None })
+    }
+
+    #[test]
+    fn test_validate() {
+        let cli_config = UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("127.0.0.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:127.0.0.2:1"), Mapping::from("2:127.0.0.2:1"), Mapping::from("3:127.0.0.2:2"))),
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(cli_config, UserConfiguration::default(), UserConfiguration::default()).unwrap();
+        assert_eq!(cfg.validate_with(&interfaces()), vec!(ValidationIssue {
+            severity: Severity::Warning,
+            message: "Art-Net universes 0:0:1 (1) and 0:0:2 (2) both map to KiNET destination 127.0.0.2 port 1".to_string(),
+        }));
+
+        let cli_config = UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("192.0.2.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:127.0.0.2:1"))),
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(cli_config, UserConfiguration::default(), UserConfiguration::default()).unwrap();
+        assert_eq!(cfg.validate_with(&interfaces()), vec!(ValidationIssue {
+            severity: Severity::Error,
+            message: "KiNET send address 192.0.2.1 is not assigned to any local network interface".to_string(),
+        }));
+
+        // A universe mapped to two places loads with the later mapping, but is an error
+        let cli_config = UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("127.0.0.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:127.0.0.2:1"), Mapping::from("1:127.0.0.3:1"))),
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(cli_config, UserConfiguration::default(), UserConfiguration::default()).unwrap();
+        assert_eq!(cfg.destinations.kinet[&1].kinet_address, "127.0.0.3");
+        assert_eq!(cfg.validate_with(&interfaces()), vec!(ValidationIssue {
+            severity: Severity::Error,
+            message: "Art-Net universe 0:0:1 (1) is mapped to both 127.0.0.2 port 1 and 127.0.0.3 port 1".to_string(),
+        }));
+    }
+
+    #[test]
+    fn test_named_supplies_and_fixtures() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "supplies": {
+                "stage-left-pds": { "ip": "10.0.0.1", "ports": 16, "protocol": "v2" },
+                "booth-pds": { "ip": "10.0.0.2", "protocol": "v1", "trim_dmx_out": true }
+            },
+            "fixtures": {
+                "cove-left": { "supply": "stage-left-pds", "port": 3, "channels": 96, "start_code": 4095 }
+            },
+            "mappings": [
+                "5:10.0.0.3:2",
+                { "universe": "1:0:15", "supply": "stage-left-pds", "port": 4, "channels": 150, "start_code": 23 },
+                { "universe": 2, "fixture": "cove-left" },
+                { "universe": 3, "supply": "booth-pds" }
+            ]
+        }"#).unwrap();
+
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config).unwrap();
+        let destinations = cfg.destinations.kinet;
+        assert_eq!(destinations.len(), 4);
+        assert_eq!((destinations[&0x005].kinet_address.as_str(), destinations[&0x005].kinet_port), ("10.0.0.3", 2));
+        assert_eq!((destinations[&0x10F].kinet_address.as_str(), destinations[&0x10F].kinet_port), ("10.0.0.1", 4));
+        assert_eq!((destinations[&0x002].kinet_address.as_str(), destinations[&0x002].kinet_port), ("10.0.0.1", 3));
+        assert_eq!((destinations[&0x003].kinet_address.as_str(), destinations[&0x003].kinet_port), ("10.0.0.2", 0));
+        assert_eq!(destinations[&0x005].kinet_channels, None);
+        assert_eq!(destinations[&0x10F].kinet_channels, Some(150));
+        assert_eq!(destinations[&0x002].kinet_channels, Some(96));
+        assert!(destinations[&0x003].kinet_trim_dmx_out);
+        assert!(!destinations[&0x002].kinet_trim_dmx_out);
+        assert_eq!(destinations[&0x10F].kinet_start_code, Some(23));
+        assert_eq!(destinations[&0x002].kinet_start_code, Some(4095));
+        assert_eq!(destinations[&0x005].kinet_start_code, None);
+
+        let mut supplies = HashMap::new();
+        supplies.insert("v1".to_string(), Supply { ip: "10.0.0.1".to_string(), ports: 16, protocol: Some(KinetProtocol::V1), trim_dmx_out: false });
+        supplies.insert("v2".to_string(), Supply { ip: "10.0.0.2".to_string(), ports: 4, protocol: Some(KinetProtocol::V2), trim_dmx_out: false });
+        let mut fixtures = HashMap::new();
+        fixtures.insert("cove".to_string(), Fixture { supply: "v2".to_string(), port: 1, channels: None, start_code: None });
+
+        let bad_cases = vec!(
+            r#"{ "universe": 1, "supply": "missing" }"#, // unknown supply
+            r#"{ "universe": 1, "fixture": "missing" }"#, // unknown fixture
+            r#"{ "universe": 1 }"#, // no supply or fixture
+            r#"{ "universe": 1, "supply": "v2", "fixture": "cove" }"#, // both supply and fixture
+            r#"{ "universe": 1, "fixture": "cove", "port": 2 }"#, // port given twice
+            r#"{ "universe": 1, "supply": "v1", "port": 2 }"#, // v1 supplies have no ports
+            r#"{ "universe": 1, "supply": "v2" }"#, // v2 supplies need a port
+            r#"{ "universe": 1, "supply": "v2", "port": 5 }"#, // port out of range for supply
+            r#"{ "universe": 1, "supply": "v2", "port": 1, "channels": 0 }"#, // no channels
+            r#"{ "universe": 1, "supply": "v2", "port": 1, "channels": 513 }"#, // too many channels
+            r#"{ "universe": 1, "supply": "v1", "start_code": 23 }"#, // v1 has no start code
+        );
+
+        for case in bad_cases {
+            let mapping: Mapping = serde_json::from_str(case).unwrap();
+            mapping.to_destinations(&supplies, &fixtures, &HashMap::new()).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+        }
+    }
+
+    #[test]
+    fn test_opc_mappings() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "opc_servers": {
+                "fcserver": { "address": "localhost" },
+                "other": { "address": "10.0.0.5:7891" }
+            },
+            "mappings": [
+                "1:127.0.0.2:1",
+                { "universe": 1, "opc_server": "fcserver" },
+                { "universe": "0:0:2", "opc_server": "fcserver", "opc_channel": 1, "pixel_offset": 0, "pixels": 100 },
+                { "universe": 3, "opc_server": "fcserver", "opc_channel": 1, "pixel_offset": 90 },
+                { "universe": 4, "opc_server": "other", "opc_channel": 1, "pixel_offset": 90 }
+            ]
+        }"#).unwrap();
+
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.destinations.kinet.len(), 1);
+        assert_eq!(cfg.destinations.opc.len(), 4);
+        assert_eq!(cfg.destinations.opc[&0x001].opc_address, "localhost:7890");
+        assert_eq!(cfg.destinations.opc[&0x002], OpcDestination {
+            port_address: PortAddress::from_flat(2).unwrap(),
+            opc_address: "localhost:7890".to_string(),
+            opc_channel: 1,
+            pixel_offset: 0,
+            pixels: Some(100),
+        });
+        assert_eq!(cfg.destinations.opc[&0x004].opc_address, "10.0.0.5:7891");
+
+        let overlaps: Vec<String> = cfg.validate_with(&interfaces()).into_iter().map(|issue| issue.message).collect();
+        assert_eq!(overlaps, vec!("Art-Net universes 0:0:2 (2) and 0:0:3 (3) both write to pixels 90-99 on OPC server localhost:7890 channel 1"));
+
+        let mut opc_servers = HashMap::new();
+        opc_servers.insert("fcserver".to_string(), OpcServer { address: "127.0.0.1".to_string() });
+        opc_servers.insert("bad".to_string(), OpcServer { address: "127.0.0.1:port".to_string() });
+        let mut supplies = HashMap::new();
+        supplies.insert("pds".to_string(), Supply { ip: "10.0.0.1".to_string(), ports: 16, protocol: None, trim_dmx_out: false });
+
+        let bad_cases = vec!(
+            r#"{ "universe": 1, "opc_server": "missing" }"#, // unknown server
+            r#"{ "universe": 1, "opc_server": "bad" }"#, // bad server address
+            r#"{ "universe": 1, "opc_server": "fcserver", "supply": "pds" }"#, // both OPC and KiNET
+            r#"{ "universe": 1, "opc_server": "fcserver", "port": 1 }"#, // KiNET option on OPC
+            r#"{ "universe": 1, "supply": "pds", "port": 1, "pixels": 10 }"#, // OPC option on KiNET
+            r#"{ "universe": 1, "opc_server": "fcserver", "pixels": 171 }"#, // more pixels than a universe holds
+            r#"{ "universe": 1, "opc_server": "fcserver", "pixel_offset": 21800 }"#, // past the end of an OPC message
+            r#"{ "universe": "1:16", "opc_server": "fcserver" }"#, // bad universe
+        );
+
+        for case in bad_cases {
+            let mapping: Mapping = serde_json::from_str(case).unwrap();
+            mapping.to_destinations(&supplies, &HashMap::new(), &opc_servers).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+        }
+    }
+
+    #[test]
+    fn test_sacn_mappings() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "sacn_source_name": "Stage bridge",
+            "sacn_priority": 120,
+            "mappings": [
+                "1:127.0.0.2:1",
+                { "universe": 1, "sacn_universe": 1 },
+                { "universe": 2, "sacn_universe": 300, "sacn_unicast": "127.0.0.3", "sacn_priority": 150 }
+            ]
+        }"#).unwrap();
+        let cli_config = UserConfiguration {
+            sacn_cid: Some("6ba7b810-9dad-11d1-80b4-00c04fd430c8".to_string()),
+            ..UserConfiguration::default()
+        };
+
+        let cfg = Configuration::from_user_configs(cli_config, UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.sacn_source_name, "Stage bridge");
+        assert_eq!(cfg.sacn_priority, 120);
+        assert_eq!(cfg.sacn_cid[..4], [0x6b, 0xa7, 0xb8, 0x10]);
+        assert_eq!(cfg.destinations.kinet.len(), 1);
+        assert_eq!(cfg.destinations.sacn[&0x001].sacn_socket_addr, "239.255.0.1:5568".parse().unwrap());
+        assert_eq!(cfg.destinations.sacn[&0x001].sacn_priority, None);
+        assert_eq!(cfg.destinations.sacn[&0x002], SacnDestination {
+            port_address: PortAddress::from_flat(2).unwrap(),
+            sacn_universe: 300,
+            sacn_unicast: Some("127.0.0.3".to_string()),
+            sacn_socket_addr: "127.0.0.3:5568".parse().unwrap(),
+            sacn_priority: Some(150),
+        });
+
+        // Without a CID, one is derived from the source name
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("127.0.0.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:127.0.0.2:1"))),
+            ..UserConfiguration::default()
+        }).unwrap();
+        assert_eq!(cfg.sacn_source_name, DEFAULT_SACN_SOURCE_NAME);
+        assert_eq!(cfg.sacn_cid, sacn::default_cid(DEFAULT_SACN_SOURCE_NAME));
+        assert_eq!(cfg.sacn_priority, sacn::DEFAULT_PRIORITY);
+
+        let bad_cases = vec!(
+            r#"{ "universe": 1, "sacn_universe": 0 }"#, // sACN universes start at 1
+            r#"{ "universe": 1, "sacn_universe": 64000 }"#, // past the last sACN universe
+            r#"{ "universe": 1, "sacn_universe": 1, "sacn_priority": 201 }"#, // priority out of range
+            r#"{ "universe": 1, "sacn_priority": 100 }"#, // no sACN universe
+            r#"{ "universe": 1, "sacn_universe": 1, "supply": "pds" }"#, // both sACN and KiNET
+            r#"{ "universe": 1, "sacn_universe": 1, "opc_server": "fcserver" }"#, // both sACN and OPC
+        );
+
+        for case in bad_cases {
+            let mapping: Mapping = serde_json::from_str(case).unwrap();
+            mapping.to_destinations(&HashMap::new(), &HashMap::new(), &HashMap::new()).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+        }
+    }
+
+    #[test]
+    fn test_artnet_mappings() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "mappings": [
+                "1:127.0.0.2:1",
+                { "universe": 1, "artnet_node": "127.0.0.3" },
+                { "universe": "0:0:2", "artnet_node": "127.0.0.4", "artnet_universe": "1:2:3" },
+                { "universe": 3, "artnet_node": "127.0.0.4", "artnet_universe": 291 }
+            ]
+        }"#).unwrap();
+
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.destinations.kinet.len(), 1);
+        assert_eq!(cfg.destinations.artnet[&0x001].artnet_socket_addr, "127.0.0.3:6454".parse().unwrap());
+        assert_eq!(cfg.destinations.artnet[&0x001].artnet_output_universe, PortAddress::from_flat(1).unwrap());
+        assert_eq!(cfg.destinations.artnet[&0x002], ArtnetDestination {
+            port_address: PortAddress::from_flat(2).unwrap(),
+            artnet_node: "127.0.0.4".to_string(),
+            artnet_socket_addr: "127.0.0.4:6454".parse().unwrap(),
+            artnet_output_universe: PortAddress::new(1, 2, 3).unwrap(),
+        });
+
+        let issues: Vec<String> = cfg.validate_with(&interfaces()).into_iter().map(|issue| issue.message).collect();
+        assert_eq!(issues, vec!("Art-Net universes 0:0:2 (2) and 0:0:3 (3) both map to Art-Net node 127.0.0.4 universe 1:2:3"));
+
+        // Relaying back to ourselves onto a universe that is relayed again would loop
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "mappings": [
+                { "universe": 1, "artnet_node": "127.0.0.1", "artnet_universe": 2 },
+                { "universe": 2, "artnet_node": "127.0.0.3" }
+            ]
+        }"#).unwrap()).unwrap();
+        let issues = cfg.validate_with(&interfaces());
+        assert_eq!(issues.len(), 1);
+        assert_eq!(issues[0].severity, Severity::Error);
+
+        let bad_cases = vec!(
+            r#"{ "universe": 1, "artnet_universe": 2 }"#, // no Art-Net node
+            r#"{ "universe": 1, "artnet_node": "127.0.0.3", "artnet_universe": "0:16:0" }"#, // bad output universe
+            r#"{ "universe": 1, "artnet_node": "127.0.0.3", "port": 1 }"#, // both Art-Net and KiNET
+            r#"{ "universe": 1, "artnet_node": "127.0.0.3", "sacn_universe": 1 }"#, // both Art-Net and sACN
+        );
+
+        for case in bad_cases {
+            let mapping: Mapping = serde_json::from_str(case).unwrap();
+            mapping.to_destinations(&HashMap::new(), &HashMap::new(), &HashMap::new()).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+        }
+    }
+
+    #[test]
+    fn test_ddp_mappings() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "mappings": [
+                { "universe": 10, "ddp_device": "127.0.0.5", "ddp_universes": 4 },
+                { "universe": "0:1:0", "ddp_device": "127.0.0.5", "pixel_offset": 680, "pixels": 100 },
+                { "universe": 17, "ddp_device": "127.0.0.6", "pixel_offset": 50, "ddp_universes": 2 }
+            ]
+        }"#).unwrap();
+
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.destinations.ddp.len(), 7);
+        assert_eq!(cfg.destinations.ddp[&10].pixel_offset, 0);
+        assert_eq!(cfg.destinations.ddp[&13], DdpDestination {
+            port_address: PortAddress::from_flat(13).unwrap(),
+            ddp_device: "127.0.0.5".to_string(),
+            ddp_socket_addr: "127.0.0.5:4048".parse().unwrap(),
+            pixel_offset: 510,
+            pixels: None,
+        });
+        assert_eq!(cfg.destinations.ddp[&16].pixel_range(), 680..780);
+        assert_eq!(cfg.destinations.ddp[&18].pixel_range(), 220..390);
+        assert!(cfg.validate_with(&interfaces()).is_empty());
+
+        let mut supplies = HashMap::new();
+        supplies.insert("pds".to_string(), Supply { ip: "10.0.0.1".to_string(), ports: 16, protocol: None, trim_dmx_out: false });
+
+        let bad_cases = vec!(
+            r#"{ "universe": 1, "ddp_universes": 2 }"#, // no DDP device
+            r#"{ "universe": 1, "ddp_device": "127.0.0.5", "ddp_universes": 0 }"#, // no universes
+            r#"{ "universe": 1, "ddp_device": "127.0.0.5", "pixels": 171 }"#, // more pixels than a universe holds
+            r#"{ "universe": 32767, "ddp_device": "127.0.0.5", "ddp_universes": 2 }"#, // past the last Art-Net universe
+            r#"{ "universe": 1, "ddp_device": "127.0.0.5", "supply": "pds" }"#, // both DDP and KiNET
+            r#"{ "universe": 1, "sacn_universe": 1, "pixels": 10 }"#, // pixel option on sACN
+        );
+
+        for case in bad_cases {
+            let mapping: Mapping = serde_json::from_str(case).unwrap();
+            mapping.to_destinations(&supplies, &HashMap::new(), &HashMap::new()).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+        }
+    }
+
+    #[test]
+    fn test_kinet_inputs() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "kinet_inputs": [
+                { "port": 1, "universe": 10, "artnet_node": "127.0.0.3" },
+                { "source": "10.0.0.9", "port": 1, "universe": "0:0:11", "artnet_node": "127.0.0.3" },
+                { "universe": 12, "artnet_node": "127.255.255.255" }
+            ]
+        }"#).unwrap();
+
+        // KiNET inputs alone are enough to run the bridge in reverse
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config.clone()).unwrap();
+        assert!(cfg.destinations.is_empty());
+        assert_eq!(cfg.kinet_inputs.len(), 3);
+        assert_eq!(cfg.kinet_inputs[1], KinetSource {
+            kinet_source: Some(Ipv4Addr::new(10, 0, 0, 9)),
+            kinet_port: 1,
+            port_address: PortAddress::from_flat(11).unwrap(),
+            artnet_node: "127.0.0.3".to_string(),
+            artnet_socket_addr: "127.0.0.3:6454".parse().unwrap(),
+        });
+        assert_eq!(cfg.kinet_inputs[2].kinet_port, 0);
+        assert!(cfg.kinet_inputs[1].matches("10.0.0.9".parse().unwrap(), 1));
+        assert!(!cfg.kinet_inputs[1].matches("10.0.0.8".parse().unwrap(), 1));
+        assert!(cfg.kinet_inputs[0].matches("10.0.0.8".parse().unwrap(), 1));
+
+        let bad_cases = vec!(
+            r#"[{ "port": 1, "universe": 10, "artnet_node": "127.0.0.3" }, { "port": 1, "universe": 11, "artnet_node": "127.0.0.3" }]"#, // same port twice
+            r#"[{ "source": "controller", "universe": 10, "artnet_node": "127.0.0.3" }]"#, // source isn't an address
+            r#"[{ "universe": "0:16:0", "artnet_node": "127.0.0.3" }]"#, // bad universe
+        );
+
+        for case in bad_cases {
+            let cfg = UserConfiguration { kinet_inputs: serde_json::from_str(case).unwrap(), ..file_config.clone() };
+            Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), cfg)
+                .err().unwrap_or_else(|| panic!("Expected case to fail, but it didn't: {}", case));
         }
+    }
+
+    #[test]
+    fn test_write_file_mappings() {
+        let path = std::env::temp_dir().join(format!("artnet-bridge-mappings-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "artnet_receive_ip": "127.0.0.1", "kinet_send_ip": "127.0.0.1", "mappings": ["1:10.0.0.1"], "verbose": 1 }"#).unwrap();
+        let cli_config = UserConfiguration { config_file: Some(path.to_string_lossy().into_owned()), ..UserConfiguration::default() };
+
+        let mappings = serde_json::json!(["2:10.0.0.2", { "universe": 3, "sacn_universe": 3 }]);
+        let (cfg, _) = Configuration::write_file_mappings(&cli_config, mappings.clone()).unwrap();
+        assert_eq!(cfg.destinations.kinet[&2].kinet_address, "10.0.0.2");
+        assert_eq!(Configuration::read_file_mappings(&cli_config).unwrap().1, mappings);
+        assert_eq!(UserConfiguration::from_file(&path).unwrap().verbose, 1);
+
+        // A mapping that doesn't load leaves the file as it was
+        assert!(Configuration::write_file_mappings(&cli_config, serde_json::json!(["2:10.0.0.2:99"])).is_err());
+        assert_eq!(Configuration::read_file_mappings(&cli_config).unwrap().1, mappings);
+        fs::remove_file(&path).unwrap();
+
+        let toml_config = UserConfiguration { config_file: Some("show.toml".to_string()), ..UserConfiguration::default() };
+        Configuration::read_file_mappings(&toml_config).expect_err("Expected a TOML file to be rejected");
+
+        // Only the mappings are rewritten, so the rest of the file keeps its order and layout
+        let original = r#"{
+  "kinet_send_ip": "127.0.0.1",
+  "mappings": [ "1:10.0.0.1", { "universe": "0:0:5", "sacn_universe": 5 } ],
+  "artnet_receive_ip": "127.0.0.1",
+  "supplies": { "mappings": { "ip": "10.0.0.9" } }
+}
+"#;
+        fs::write(&path, original).unwrap();
+        let mappings = serde_json::json!(["2:10.0.0.2", { "universe": 3, "supply": "mappings" }]);
+        Configuration::write_file_mappings(&cli_config, mappings.clone()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), r#"{
+  "kinet_send_ip": "127.0.0.1",
+  "mappings": [
+      "2:10.0.0.2",
+      {"supply":"mappings","universe":3}
+  ],
+  "artnet_receive_ip": "127.0.0.1",
+  "supplies": { "mappings": { "ip": "10.0.0.9" } }
+}
+"#);
+        assert_eq!(Configuration::read_file_mappings(&cli_config).unwrap().1, mappings);
+
+        // A file without any mappings yet gets them added at the end
+        fs::write(&path, "{ \"artnet_receive_ip\": \"127.0.0.1\", \"kinet_send_ip\": \"127.0.0.1\" }\n").unwrap();
+        Configuration::write_file_mappings(&cli_config, serde_json::json!(["1:10.0.0.1"])).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(),
+            "{ \"artnet_receive_ip\": \"127.0.0.1\", \"kinet_send_ip\": \"127.0.0.1\",\n    \"mappings\": [\n        \"1:10.0.0.1\"\n    ]\n}\n");
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_top_level_value() {
+        let contents = r#"{ "a": "mappings", "b\"mappings": [1, {"mappings": 2}], "mappings" : [ "x, y" ] , "c": {} }"#;
+        assert_eq!(top_level_value(contents, "mappings").map(|range| &contents[range]), Some(r#"[ "x, y" ]"#));
+        assert_eq!(top_level_value(contents, "c").map(|range| &contents[range]), Some("{}"));
+        assert_eq!(top_level_value(r#"{ "a": 1 }"#, "mappings"), None);
+    }
+
+    #[test]
+    fn test_scenes() {
+        let file_config: UserConfiguration = serde_json::from_str(r#"{
+            "artnet_receive_ip": "127.0.0.1",
+            "kinet_send_ip": "127.0.0.1",
+            "mappings": [ "1:127.0.0.2:1" ],
+            "osc_port": 8000,
+            "scenes": {
+                "1": { "master": 0.5, "levels": { "1": [255, 128], "0:1:0": [10] } },
+                "house": { "blackout": false }
+            }
+        }"#).unwrap();
+
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config.clone()).unwrap();
+        assert_eq!(cfg.osc_port, Some(8000));
+        assert_eq!(cfg.scenes.len(), 2);
+        assert_eq!(cfg.scenes["1"].master, Some(0.5));
+        assert_eq!(cfg.scenes["1"].levels[&16], vec!(10));
+        assert_eq!(cfg.scenes["house"].blackout, Some(false));
+
+        let bad_cases = vec!(
+            r#"{ "bad": { "master": 1.5 } }"#, // master out of range
+            r#"{ "bad": { "levels": { "0:16:0": [0] } } }"#, // bad universe
+            r#"{ "bad": { "levels": { "1": [] }, "channels": 3 } }"#, // unknown field
+        );
 
+        // Each case has to be rejected, either as it's read or when the configuration is built
         for case in bad_cases {
-            mappings_to_destinations(vec!(case.to_string())).expect_err(format!("Expected case to fail, but it didn't: {}", case).as_str());
+            let result = serde_json::from_str(case)
+                .map_err(Error::from)
+                .and_then(|scenes| {
+                    let cfg = UserConfiguration { scenes, ..file_config.clone() };
+                    Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), cfg)
+                });
+            result.err().unwrap_or_else(|| panic!("Expected case to fail, but it didn't: {}", case));
+        }
+    }
+
+    #[test]
+    fn test_config_formats() {
+        let json = r#"{
+            "artnet_receive_ip": "192.168.1.1",
+            "kinet_send_ip": "10.0.0.1",
+            "supplies": { "pds": { "ip": "10.0.0.2", "protocol": "v2" } },
+            "mappings": [ "0:10.32.152.122:0", { "universe": "0:0:1", "supply": "pds", "port": 2 } ]
+        }"#;
+        let toml = r#"
+            # Comments are allowed in TOML
+            artnet_receive_ip = "192.168.1.1"
+            kinet_send_ip = "10.0.0.1"
+            mappings = [ "0:10.32.152.122:0", { universe = "0:0:1", supply = "pds", port = 2 } ]
+
+            [supplies.pds]
+            ip = "10.0.0.2"
+            protocol = "v2"
+        "#;
+        let yaml = r#"
+            # Comments are allowed in YAML
+            artnet_receive_ip: 192.168.1.1
+            kinet_send_ip: 10.0.0.1
+            supplies:
+              pds: { ip: 10.0.0.2, protocol: v2 }
+            mappings:
+              - "0:10.32.152.122:0"
+              - universe: "0:0:1"
+                supply: pds
+                port: 2
+        "#;
+
+        for (contents, format) in [(json, ConfigFormat::Json), (toml, ConfigFormat::Toml), (yaml, ConfigFormat::Yaml)] {
+            let cfg = UserConfiguration::parse(contents, format).unwrap();
+            assert_eq!(cfg.artnet_receive_ip.as_deref(), Some("192.168.1.1"), "{:?}", format);
+            assert_eq!(cfg.kinet_send_ip.as_deref(), Some("10.0.0.1"), "{:?}", format);
+            assert_eq!(cfg.supplies["pds"], Supply { ip: "10.0.0.2".to_string(), ports: 16, protocol: Some(KinetProtocol::V2), trim_dmx_out: false });
+            assert_eq!(cfg.mappings.unwrap(), vec!(
+                Mapping::MapString("0:10.32.152.122:0".to_string()),
+                Mapping::Named(NamedMapping {
+                    universe: UniverseSpec::Text("0:0:1".to_string()),
+                    supply: Some("pds".to_string()), fixture: None, port: Some(2), channels: None, start_code: None,
+                    opc_server: None, opc_channel: None, pixel_offset: None, pixels: None,
+                    sacn_universe: None, sacn_unicast: None, sacn_priority: None,
+                    artnet_node: None, artnet_universe: None, ddp_device: None, ddp_universes: None,
+                }),
+            ), "{:?}", format);
+        }
+
+        assert_eq!(ConfigFormat::from_path(Path::new("show.TOML")), ConfigFormat::Toml);
+        assert_eq!(ConfigFormat::from_path(Path::new("show.yml")), ConfigFormat::Yaml);
+        assert_eq!(ConfigFormat::from_path(Path::new("show.json")), ConfigFormat::Json);
+    }
+
+    #[test]
+    fn test_config_error_locations() {
+        // A typo in the third mapping, on line 5 of each file
+        let json = "{\n\"mappings\": [\n\"10.0.0.1\",\n\"10.0.0.2\",\n{ \"universe\": 1, \"suply\": \"pds\" }\n]\n}";
+        let toml = "mappings = [\n\"10.0.0.1\",\n\"10.0.0.2\",\n\n{ universe = 1, suply = \"pds\" }\n]";
+        let yaml = "mappings:\n  - 10.0.0.1\n  - 10.0.0.2\n  -\n    suply: pds\n";
+
+        for (contents, format) in [(json, ConfigFormat::Json), (toml, ConfigFormat::Toml), (yaml, ConfigFormat::Yaml)] {
+            let message = UserConfiguration::parse(contents, format).unwrap_err().to_string();
+            assert!(message.contains("suply"), "{:?} error doesn't name the field: {}", format, message);
+            assert!(message.contains("line 5"), "{:?} error doesn't point to line 5: {}", format, message);
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn test_env_config() {
+        let vars = vec!(
+            ("PATH".to_string(), "/usr/bin".to_string()),
+            ("ARTNET_BRIDGE_ARTNET_RECEIVE_IP".to_string(), "127.0.0.1".to_string()),
+            ("ARTNET_BRIDGE_KINET_SEND_IP".to_string(), "127.0.0.1".to_string()),
+            ("ARTNET_BRIDGE_MAPPINGS".to_string(), "1:10.0.0.1:1, 2:10.0.0.1:2\n3:10.0.0.1:3".to_string()),
+            ("ARTNET_BRIDGE_VERBOSE".to_string(), "1".to_string()),
+            ("ARTNET_BRIDGE_KINET_FLAGS".to_string(), "0x0100".to_string()),
+            ("ARTNET_BRIDGE_SACN_SOURCE_NAME".to_string(), "Stage bridge".to_string()),
+            ("ARTNET_BRIDGE_SACN_PRIORITY".to_string(), "150".to_string()),
+            ("ARTNET_BRIDGE_OSC_PORT".to_string(), "8000".to_string()),
+            ("ARTNET_BRIDGE_HTTP_PORT".to_string(), "8080".to_string()),
+            ("ARTNET_BRIDGE_HTTP_IP".to_string(), "lo".to_string()),
+        );
+        let env_config = UserConfiguration::from_env_vars(vars).unwrap();
+        assert_eq!(env_config.mappings.as_ref().unwrap().len(), 3);
+        assert_eq!(env_config.verbose, 1);
+        assert_eq!(env_config.sacn_source_name.as_deref(), Some("Stage bridge"));
+        assert_eq!(env_config.sacn_priority, Some(150));
+        assert_eq!(env_config.osc_port, Some(8000));
+        assert_eq!(env_config.http_port, Some(8080));
+        assert_eq!(env_config.http_ip.as_deref(), Some("lo"));
+
+        UserConfiguration::from_env_vars(vec!(("ARTNET_BRIDGE_VERBOSE".to_string(), "lots".to_string())))
+            .expect_err("Expected a bad verbosity to fail");
+        UserConfiguration::from_env_vars(vec!(("ARTNET_BRIDGE_QUIET".to_string(), "127".to_string())))
+            .expect_err("Expected an out of range verbosity to fail");
+        UserConfiguration::from_env_vars(vec!(("ARTNET_BRIDGE_MAPING".to_string(), "1:10.0.0.1".to_string())))
+            .expect_err("Expected an unknown variable to fail");
+
+        // Single values come from the CLI, then the environment, then the file; mappings are combined
+        let cli_config = UserConfiguration {
+            kinet_send_ip: Some("0.0.0.0".to_string()),
+            mappings: Some(vec!(Mapping::from("4:10.0.0.1:4"))),
+            ..UserConfiguration::default()
+        };
+        let file_config = UserConfiguration {
+            artnet_receive_ip: Some("192.0.2.1".to_string()),
+            kinet_send_ip: Some("192.0.2.1".to_string()),
+            mappings: Some(vec!(Mapping::from("5:10.0.0.1:5"))),
+            verbose: 1,
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(cli_config, env_config, file_config).unwrap();
+        assert_eq!(cfg.artnet_receive_ip, "127.0.0.1");
+        assert_eq!(cfg.kinet_send_ip, "0.0.0.0");
+        assert_eq!(cfg.destinations.kinet.len(), 5);
+        assert_eq!(cfg.verbosity, 4);
+        assert_eq!(cfg.kinet_flags, 0x0100);
+        assert_eq!(cfg.kinet_timer, -1);
+
+        // A universe mapped both on the command line and in the file goes where the command line says
+        let cli_config = UserConfiguration {
+            mappings: Some(vec!(Mapping::from("1:10.0.0.1:1"))),
+            ..UserConfiguration::default()
+        };
+        let file_config = UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("127.0.0.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:10.0.0.2:1"))),
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(cli_config, UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.destinations.kinet[&1].kinet_address, "10.0.0.1");
+
+        // Counts too large to add up stop at the loudest or quietest level
+        let file_config = UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("127.0.0.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:10.0.0.1"))),
+            verbose: 127,
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.get_log_level(), Some(Level::Trace));
+        let file_config = UserConfiguration {
+            artnet_receive_ip: Some("127.0.0.1".to_string()),
+            kinet_send_ip: Some("127.0.0.1".to_string()),
+            mappings: Some(vec!(Mapping::from("1:10.0.0.1"))),
+            quiet: 127,
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), UserConfiguration::default(), file_config).unwrap();
+        assert_eq!(cfg.get_log_level(), None);
+    }
+}
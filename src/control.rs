@@ -0,0 +1,210 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::sync::{RwLock, RwLockReadGuard};
+
+use anyhow::{anyhow, Error};
+use serde::Serialize;
+
+use crate::artnet::MAX_DMX_LENGTH;
+
+// A scene from the config file: levels to hold on some universes, starting from channel 1, and
+// optionally a master level and blackout to go with them
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scene {
+    pub master: Option<f32>,
+    pub blackout: Option<bool>,
+    pub levels: BTreeMap<u16, Vec<u8>>,
+}
+
+// Live adjustments to the Art-Net passing through the bridge, made by the control interfaces and
+// applied in the receive loop before data is sent to any destination
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ControlState {
+    pub master: f32,
+    pub blackout: bool,
+    // Universes whose mappings are turned off
+    pub disabled: BTreeSet<u16>,
+    // Levels held on individual channels, numbered from 1, regardless of what the console sends
+    pub overrides: BTreeMap<u16, BTreeMap<u16, u8>>,
+    pub scene: Option<String>,
+}
+
+impl Default for ControlState {
+    fn default() -> Self {
+        ControlState {
+            master: 1.0,
+            blackout: false,
+            disabled: BTreeSet::new(),
+            overrides: BTreeMap::new(),
+            scene: None,
+        }
+    }
+}
+
+impl ControlState {
+    // Returns None when the universe is disabled. Otherwise returns the levels to send: the data as
+    // received when nothing applies to it, or a copy in `levels` with overrides, master and blackout.
+    pub fn apply<'a>(&self, port_address: u16, data: &'a [u8], levels: &'a mut Vec<u8>) -> Option<&'a [u8]> {
+        if self.disabled.contains(&port_address) {
+            return None;
+        }
+        let overrides = self.overrides.get(&port_address);
+        if overrides.is_none() && self.master >= 1.0 && !self.blackout {
+            return Some(data);
+        }
+
+        levels.clear();
+        levels.extend_from_slice(data);
+        if let Some(overrides) = overrides {
+            for (&channel, &level) in overrides {
+                let index = channel as usize - 1;
+                if levels.len() <= index {
+                    levels.resize(index + 1, 0);
+                }
+                levels[index] = level;
+            }
+        }
+
+        let master = if self.blackout { 0.0 } else { self.master };
+        if master < 1.0 {
+            for level in levels.iter_mut() {
+                *level = (*level as f32 * master).round() as u8;
+            }
+        }
+        Some(levels)
+    }
+}
+
+pub struct Control {
+    state: RwLock<ControlState>,
+    scenes: HashMap<String, Scene>,
+}
+
+impl Control {
+    pub fn new(scenes: HashMap<String, Scene>) -> Self {
+        Control { state: RwLock::new(ControlState::default()), scenes }
+    }
+
+    pub fn state(&self) -> RwLockReadGuard<'_, ControlState> {
+        self.state.read().unwrap()
+    }
+
+    pub fn set_master(&self, master: f32) -> Result<(), Error> {
+        if !(0.0..=1.0).contains(&master) {
+            return Err(anyhow!("Master level {} is out of range (must be 0-1)", master));
+        }
+        self.state.write().unwrap().master = master;
+        Ok(())
+    }
+
+    pub fn set_blackout(&self, blackout: bool) {
+        self.state.write().unwrap().blackout = blackout;
+    }
+
+    pub fn toggle_blackout(&self) {
+        let mut state = self.state.write().unwrap();
+        state.blackout = !state.blackout;
+    }
+
+    pub fn set_enabled(&self, port_address: u16, enabled: bool) {
+        let mut state = self.state.write().unwrap();
+        if enabled {
+            state.disabled.remove(&port_address);
+        } else {
+            state.disabled.insert(port_address);
+        }
+    }
+
+    // Holds a channel at a level, or releases it back to the console with None
+    pub fn set_channel(&self, port_address: u16, channel: u16, level: Option<u8>) -> Result<(), Error> {
+        if channel == 0 || channel as usize > MAX_DMX_LENGTH {
+            return Err(anyhow!("Channel {} is out of range (must be 1-{})", channel, MAX_DMX_LENGTH));
+        }
+        let mut state = self.state.write().unwrap();
+        match level {
+            Some(level) => { state.overrides.entry(port_address).or_default().insert(channel, level); },
+            None => {
+                if let Some(overrides) = state.overrides.get_mut(&port_address) {
+                    overrides.remove(&channel);
+                    if overrides.is_empty() {
+                        state.overrides.remove(&port_address);
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // A scene replaces any held channels with its own levels
+    pub fn recall_scene(&self, name: &str) -> Result<(), Error> {
+        let scene = match self.scenes.get(name) {
+            Some(scene) => scene,
+            None => return Err(anyhow!("Unknown scene {}", name)),
+        };
+
+        let mut state = self.state.write().unwrap();
+        state.overrides = scene.levels.iter()
+            .map(|(&port_address, levels)| (port_address, (1..).zip(levels.iter().copied()).collect()))
+            .collect();
+        if let Some(master) = scene.master {
+            state.master = master;
+        }
+        if let Some(blackout) = scene.blackout {
+            state.blackout = blackout;
+        }
+        state.scene = Some(name.to_string());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let control = Control::new(HashMap::new());
+        let mut levels = vec!();
+        assert_eq!(control.state().apply(1, &[100, 200], &mut levels), Some(&[100, 200][..]));
+
+        control.set_channel(1, 4, Some(255)).unwrap();
+        assert_eq!(control.state().apply(1, &[100, 200], &mut levels), Some(&[100, 200, 0, 255][..]));
+        assert_eq!(control.state().apply(2, &[100, 200], &mut levels), Some(&[100, 200][..]));
+
+        control.set_master(0.5).unwrap();
+        assert_eq!(control.state().apply(1, &[100, 200], &mut levels), Some(&[50, 100, 0, 128][..]));
+
+        control.set_blackout(true);
+        assert_eq!(control.state().apply(2, &[100, 200], &mut levels), Some(&[0, 0][..]));
+
+        control.set_enabled(2, false);
+        assert_eq!(control.state().apply(2, &[100, 200], &mut levels), None);
+        control.set_enabled(2, true);
+        control.set_blackout(false);
+        control.set_master(1.0).unwrap();
+        control.set_channel(1, 4, None).unwrap();
+        assert_eq!(*control.state(), ControlState::default());
+
+        control.set_master(1.5).expect_err("Expected a master level above 1 to fail");
+        control.set_channel(1, 513, Some(0)).expect_err("Expected channel 513 to fail");
+    }
+
+    #[test]
+    fn test_recall_scene() {
+        let mut scenes = HashMap::new();
+        let mut levels = BTreeMap::new();
+        levels.insert(3, vec!(10, 20));
+        scenes.insert("preset".to_string(), Scene { master: Some(0.8), blackout: None, levels });
+        let control = Control::new(scenes);
+
+        control.set_channel(1, 1, Some(255)).unwrap();
+        control.recall_scene("preset").unwrap();
+        let state = control.state();
+        assert_eq!(state.master, 0.8);
+        assert_eq!(state.scene.as_deref(), Some("preset"));
+        assert!(!state.overrides.contains_key(&1));
+        assert_eq!(state.overrides[&3].get(&2), Some(&20));
+        drop(state);
+
+        control.recall_scene("missing").expect_err("Expected an unknown scene to fail");
+    }
+}
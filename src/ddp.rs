@@ -0,0 +1,149 @@
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+
+use crate::config::DdpDestination;
+use crate::opc::MAX_PIXELS;
+use crate::utils;
+
+pub const DDP_PORT: u16 = 4048;
+
+const HEADER_LENGTH: usize = 10;
+const VERSION_1: u8 = 0x40;
+const PUSH: u8 = 0x01;
+// RGB pixels with 8 bits per channel
+const DATA_TYPE_RGB8: u8 = 0x0b;
+const DEFAULT_OUTPUT_DEVICE: u8 = 1;
+
+// Keep packets within a standard Ethernet frame: 480 RGB pixels each
+pub const MAX_DATA_LENGTH: usize = 1440;
+
+// A DDP data packet: flags, sequence, data type, output device, then the big-endian offset of the
+// data in bytes and its length
+pub fn data_packet(sequence: u8, push: bool, offset: u32, data: &[u8]) -> Vec<u8> {
+    let mut packet = Vec::with_capacity(HEADER_LENGTH + data.len());
+    packet.push(if push { VERSION_1 | PUSH } else { VERSION_1 });
+    packet.push(sequence & 0x0f);
+    packet.push(DATA_TYPE_RGB8);
+    packet.push(DEFAULT_OUTPUT_DEVICE);
+    packet.extend_from_slice(&offset.to_be_bytes());
+    packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+    packet.extend_from_slice(data);
+    packet
+}
+
+// Sends Art-Net universes to DDP devices over UDP. Every universe mapped to a device is written into
+// one frame of pixels, which is sent once each of them has arrived, with the push flag set on the
+// last packet so the device shows the whole frame at once. If a universe arrives again before the
+// rest, the frame is sent as it is, so one missing universe doesn't stop the others updating.
+pub struct DdpOutput {
+    socket: UdpSocket,
+    devices: HashMap<SocketAddr, Device>,
+}
+
+#[derive(Default)]
+struct Device {
+    frame: Vec<u8>,
+    received: HashSet<u16>,
+    sequence: u8,
+}
+
+impl DdpOutput {
+    pub fn new(socket: UdpSocket) -> Self {
+        DdpOutput { socket, devices: HashMap::new() }
+    }
+
+    // universes is how many make up the device's frame, from universes_for_device. Returns the
+    // number of packets sent, which is 0 while waiting for more universes.
+    pub fn send(&mut self, destination: &DdpDestination, data: &[u8], universes: usize) -> io::Result<usize> {
+        let port_address = destination.port_address.value();
+        let device = self.devices.entry(destination.ddp_socket_addr).or_default();
+
+        let mut sent = 0;
+        if device.received.contains(&port_address) {
+            sent += device.flush(&self.socket, destination.ddp_socket_addr)?;
+        }
+
+        let pixels = destination.pixels.unwrap_or(MAX_PIXELS);
+        utils::write_pixels(&mut device.frame, destination.pixel_offset as usize, Some(pixels as usize), data);
+        device.received.insert(port_address);
+
+        if device.received.len() >= universes {
+            sent += device.flush(&self.socket, destination.ddp_socket_addr)?;
+        }
+        Ok(sent)
+    }
+}
+
+// The number of universes mapped to the same device as a destination
+pub fn universes_for_device(destination: &DdpDestination, destinations: &HashMap<u16, DdpDestination>) -> usize {
+    destinations.values()
+        .filter(|other| other.ddp_socket_addr == destination.ddp_socket_addr)
+        .count()
+}
+
+impl Device {
+    fn flush(&mut self, socket: &UdpSocket, address: SocketAddr) -> io::Result<usize> {
+        self.received.clear();
+
+        let chunks = self.frame.chunks(MAX_DATA_LENGTH).count();
+        for (index, chunk) in self.frame.chunks(MAX_DATA_LENGTH).enumerate() {
+            // Sequence numbers run from 1 to 15, as 0 means they aren't used
+            self.sequence = self.sequence % 15 + 1;
+            let packet = data_packet(self.sequence, index + 1 == chunks, (index * MAX_DATA_LENGTH) as u32, chunk);
+            socket.send_to(&packet, address)?;
+        }
+        Ok(chunks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::artnet::PortAddress;
+
+    fn destination(universe: u16, ddp_socket_addr: SocketAddr, pixel_offset: u32) -> DdpDestination {
+        DdpDestination {
+            port_address: PortAddress::from_flat(universe).unwrap(),
+            ddp_device: ddp_socket_addr.ip().to_string(),
+            ddp_socket_addr,
+            pixel_offset,
+            pixels: None,
+        }
+    }
+
+    #[test]
+    fn test_data_packet() {
+        assert_eq!(data_packet(3, false, 1440, &[1, 2, 3]), vec!(0x40, 3, 0x0b, 1, 0, 0, 0x05, 0xa0, 0, 3, 1, 2, 3));
+        assert_eq!(data_packet(15, true, 0, &[])[..2], [0x41, 15]);
+    }
+
+    #[test]
+    fn test_send_frames() {
+        let receiver = UdpSocket::bind("127.0.0.1:0").unwrap();
+        let address = receiver.local_addr().unwrap();
+        let mut destinations = HashMap::new();
+        destinations.insert(1, destination(1, address, 0));
+        destinations.insert(2, destination(2, address, 170));
+        destinations.insert(3, destination(3, address, 340));
+
+        assert_eq!(universes_for_device(&destinations[&2], &destinations), 3);
+
+        let mut output = DdpOutput::new(UdpSocket::bind("127.0.0.1:0").unwrap());
+        assert_eq!(output.send(&destinations[&1], &[255; 510], 3).unwrap(), 0);
+        assert_eq!(output.send(&destinations[&2], &[255; 510], 3).unwrap(), 0);
+
+        // The last universe completes the frame of 1530 bytes, which takes two packets
+        assert_eq!(output.send(&destinations[&3], &[255; 510], 3).unwrap(), 2);
+        let mut packet = [0; 1500];
+        let length = receiver.recv(&mut packet).unwrap();
+        assert_eq!((length, packet[0], packet[1]), (HEADER_LENGTH + 1440, 0x40, 1));
+        let length = receiver.recv(&mut packet).unwrap();
+        assert_eq!((length, packet[0], packet[1]), (HEADER_LENGTH + 90, 0x41, 2));
+        assert_eq!(packet[4..10], [0, 0, 0x05, 0xa0, 0, 90]);
+
+        // A universe repeating before the others arrive sends what there is
+        assert_eq!(output.send(&destinations[&1], &[0; 510], 3).unwrap(), 0);
+        assert_eq!(output.send(&destinations[&1], &[0; 510], 3).unwrap(), 2);
+    }
+}
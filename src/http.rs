@@ -0,0 +1,592 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error};
+use log::{debug, error, info, warn};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::artnet::PortAddress;
+use crate::config::{Configuration, Destinations, UserConfiguration};
+use crate::control::Control;
+use crate::metrics;
+use crate::monitor::{self, Subscription};
+use crate::stats::{Stats, UniverseStats};
+use crate::utils::parse_universe;
+use crate::websocket;
+
+const MAX_HEADER_LINES: usize = 100;
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_BODY_LENGTH: u64 = 64 * 1024;
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+// Each connection has its own thread. A WebSocket monitor holds its thread for as long as it's
+// open, so monitors have a limit of their own and give up their connection's place once they start.
+const MAX_CONNECTIONS: usize = 32;
+const MAX_MONITORS: usize = 16;
+
+const PANEL: &str = include_str!("panel.html");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Request {
+    pub method: String,
+    pub path: String,
+    pub query: String,
+    // Header names are in lower case
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+impl Request {
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter()
+            .find(|(header, _)| header == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    pub fn json(status: u16, value: &Value) -> Self {
+        Response { status, content_type: "application/json", body: value.to_string().into_bytes() }
+    }
+
+    pub fn html(body: &str) -> Self {
+        Response { status: 200, content_type: "text/html; charset=utf-8", body: body.as_bytes().to_vec() }
+    }
+
+    pub fn error(status: u16, message: &str) -> Self {
+        Response::json(status, &json!({ "error": message }))
+    }
+
+    fn write_to(&self, stream: &mut impl Write) -> io::Result<()> {
+        write!(stream, "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.status, reason(self.status), self.content_type, self.body.len())?;
+        stream.write_all(&self.body)?;
+        stream.flush()
+    }
+}
+
+fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        400 => "Bad Request",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        415 => "Unsupported Media Type",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+// Reads one line of the request head, so a client that never sends a newline can't make it grow
+// without limit. A line cut short by the end of the stream is returned as it is.
+fn read_line(reader: &mut impl BufRead) -> Result<String, Error> {
+    let mut line = String::new();
+    let length = reader.by_ref().take(MAX_LINE_LENGTH).read_line(&mut line)?;
+    if length as u64 == MAX_LINE_LENGTH && !line.ends_with('\n') {
+        return Err(anyhow!("Request line or header is longer than {} bytes", MAX_LINE_LENGTH));
+    }
+    Ok(line)
+}
+
+// Reads one request, with a body if it has a Content-Length
+pub fn read_request(reader: &mut impl BufRead) -> Result<Request, Error> {
+    let line = read_line(reader)?;
+    let mut parts = line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next(), parts.next()) {
+        (Some(method), Some(target), Some(version)) if version.starts_with("HTTP/1.") => (method.to_string(), target),
+        _ => return Err(anyhow!("Malformed request line {:?}", line.trim_end())),
+    };
+    let (path, query) = match target.split_once('?') {
+        Some((path, query)) => (path.to_string(), query.to_string()),
+        None => (target.to_string(), String::new()),
+    };
+
+    let mut headers = vec!();
+    let mut content_length = 0;
+    for _ in 0..MAX_HEADER_LINES {
+        let header = read_line(reader)?;
+        if header.trim_end().is_empty() {
+            let mut body = vec!();
+            reader.take(content_length).read_to_end(&mut body)?;
+            if (body.len() as u64) < content_length {
+                return Err(anyhow!("Request body ended early"));
+            }
+            return Ok(Request { method, path, query, headers, body });
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            let name = name.trim().to_ascii_lowercase();
+            if name == "content-length" {
+                content_length = value.trim().parse().map_err(|_| anyhow!("Invalid Content-Length {}", value.trim()))?;
+                if content_length > MAX_BODY_LENGTH {
+                    return Err(anyhow!("Request body is too large ({} bytes)", content_length));
+                }
+            }
+            headers.push((name, value.trim().to_string()));
+        }
+    }
+    Err(anyhow!("Too many request headers"))
+}
+
+// One of a limited number of places, such as for open connections, given back when dropped
+struct Slot(Arc<AtomicUsize>);
+
+impl Slot {
+    fn take(count: &Arc<AtomicUsize>, limit: usize) -> Option<Slot> {
+        count.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |taken| if taken < limit { Some(taken + 1) } else { None })
+            .ok()
+            .map(|_| Slot(count.clone()))
+    }
+}
+
+impl Drop for Slot {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+// Serves the status and control API, handling each connection on its own thread. Connections
+// beyond MAX_CONNECTIONS are closed straight away.
+pub fn spawn(listener: TcpListener, api: Api) -> thread::JoinHandle<()> {
+    let api = Arc::new(api);
+    let connections = Arc::new(AtomicUsize::new(0));
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => match Slot::take(&connections, MAX_CONNECTIONS) {
+                    Some(slot) => {
+                        let api = api.clone();
+                        thread::spawn(move || {
+                            if let Err(e) = serve(stream, &api, slot) {
+                                debug!("HTTP connection failed: {}", e);
+                            }
+                        });
+                    },
+                    None => warn!("Refusing HTTP connection from {:?}, {} are already open", stream.peer_addr().ok(), MAX_CONNECTIONS),
+                },
+                Err(e) => error!("Could not accept HTTP connection: {}", e),
+            }
+        }
+    })
+}
+
+fn serve(mut stream: TcpStream, api: &Api, slot: Slot) -> Result<(), Error> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let peer = stream.peer_addr()?;
+
+    let request = read_request(&mut BufReader::new(&stream));
+    let response = match request {
+        Ok(request) if request.header("upgrade").is_some_and(|upgrade| upgrade.eq_ignore_ascii_case("websocket")) => {
+            return api.monitor(stream, &request, slot);
+        },
+        Ok(request) => {
+            let response = api.handle(&request);
+            debug!("{} {} from {}: {}", request.method, request.path, peer, response.status);
+            response
+        },
+        Err(e) => {
+            warn!("Could not read HTTP request from {}: {}", peer, e);
+            Response::error(400, &e.to_string())
+        }
+    };
+    response.write_to(&mut stream)?;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MasterRequest {
+    master: f32,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct BlackoutRequest {
+    blackout: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MappingRequest {
+    enabled: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct FileMappingsRequest {
+    mappings: Vec<Value>,
+}
+
+pub struct Api {
+    // Settings that only change on restart, captured when the bridge starts
+    settings: Value,
+    // Used to find and check the config file when mappings are edited
+    cli_args: UserConfiguration,
+    // Held while the config file is being rewritten, so saves don't overlap
+    saving: Mutex<()>,
+    // Open WebSocket monitors, which are counted apart from other connections
+    monitors: Arc<AtomicUsize>,
+    destinations: Arc<RwLock<Destinations>>,
+    control: Arc<Control>,
+    stats: Arc<Stats>,
+}
+
+impl Api {
+    pub fn new(settings: Value, cli_args: UserConfiguration, destinations: Arc<RwLock<Destinations>>, control: Arc<Control>, stats: Arc<Stats>) -> Self {
+        Api { settings, cli_args, saving: Mutex::new(()), monitors: Arc::new(AtomicUsize::new(0)), destinations, control, stats }
+    }
+
+    pub fn settings(cfg: &Configuration) -> Value {
+        let mut scenes: Vec<&String> = cfg.scenes.keys().collect();
+        scenes.sort();
+        json!({
+            "artnet_receive_ip": cfg.artnet_receive_ip,
+            "artnet_receive_addr": cfg.artnet_receive_addr.to_string(),
+            "kinet_send_ip": cfg.kinet_send_ip,
+            "kinet_send_addr": cfg.kinet_send_addr.to_string(),
+            "kinet_flags": cfg.kinet_flags,
+            "kinet_timer": cfg.kinet_timer,
+            "sacn_source_name": cfg.sacn_source_name,
+            "sacn_priority": cfg.sacn_priority,
+            "osc_port": cfg.osc_port,
+            "http_port": cfg.http_port,
+            "http_addr": cfg.http_addr.to_string(),
+            "scenes": scenes,
+            "kinet_inputs": cfg.kinet_inputs.iter().map(|input| json!({
+                "kinet": input.to_string(),
+                "universe": input.port_address.value(),
+                "artnet": input.port_address.to_string(),
+                "artnet_node": input.artnet_node,
+                "socket_address": input.artnet_socket_addr.to_string(),
+            })).collect::<Vec<Value>>(),
+        })
+    }
+
+    pub fn handle(&self, request: &Request) -> Response {
+        if let Err(response) = check_request(request) {
+            return response;
+        }
+        let parts: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+        let result = match (request.method.as_str(), parts.as_slice()) {
+            ("GET", [""]) => return Response::html(PANEL),
+            ("GET", ["api", "config"]) => Ok(self.config()),
+            ("GET", ["api", "config", "mappings"]) => Configuration::read_file_mappings(&self.cli_args)
+                .map(|(file, mappings)| json!({ "file": file, "mappings": mappings })),
+            ("PUT", ["api", "config", "mappings"]) => parse_body::<FileMappingsRequest>(&request.body)
+                .and_then(|body| self.save_file_mappings(body.mappings)),
+            ("GET", ["api", "universes"]) => Ok(self.universes(None)),
+            ("GET", ["api", "universes", universe]) => {
+                let universe = parse_universe(universe)
+                    .and_then(|universe| self.universes(Some(universe))
+                        .get(0).cloned()
+                        .ok_or_else(|| anyhow!("Nothing has been received on universe {}", universe)));
+                return match universe {
+                    Ok(universe) => Response::json(200, &universe),
+                    Err(e) => Response::error(404, &e.to_string()),
+                };
+            },
+            ("GET", ["api", "destinations"]) => Ok(self.destinations()),
+            ("GET", ["api", "monitor"]) => return Response::error(400, "The monitor is only available over a WebSocket"),
+            ("GET", ["api", "control"]) => Ok(self.control()),
+            ("GET", ["metrics"]) => return Response {
+                status: 200,
+                content_type: metrics::CONTENT_TYPE,
+                body: metrics::render(&self.stats, &self.control.state(), SystemTime::now()).into_bytes(),
+            },
+            ("POST", ["api", "master"]) => parse_body::<MasterRequest>(&request.body)
+                .and_then(|body| self.control.set_master(body.master))
+                .map(|_| self.control()),
+            ("POST", ["api", "blackout"]) => parse_body::<BlackoutRequest>(&request.body)
+                .map(|body| self.control.set_blackout(body.blackout))
+                .map(|_| self.control()),
+            ("POST", ["api", "mappings", universe]) => parse_universe(universe)
+                .and_then(|universe| parse_body::<MappingRequest>(&request.body)
+                    .map(|body| self.control.set_enabled(universe, body.enabled)))
+                .map(|_| self.control()),
+            (_, [""]) | (_, ["api", "config"]) | (_, ["api", "config", "mappings"]) | (_, ["api", "universes"])
+                | (_, ["api", "universes", _]) | (_, ["api", "destinations"]) | (_, ["api", "monitor"]) | (_, ["api", "control"])
+                | (_, ["api", "master"]) | (_, ["api", "blackout"]) | (_, ["api", "mappings", _]) | (_, ["metrics"]) =>
+                return Response::error(405, &format!("{} isn't supported on {}", request.method, request.path)),
+            _ => return Response::error(404, &format!("Nothing at {}", request.path)),
+        };
+
+        match result {
+            Ok(value) => Response::json(200, &value),
+            Err(e) => Response::error(400, &e.to_string()),
+        }
+    }
+
+    // Upgrades a connection to /api/monitor to a WebSocket that streams levels as they arrive
+    // Once the handshake is done, the connection's place is given up for one of the monitors'
+    fn monitor(&self, mut stream: TcpStream, request: &Request, connection: Slot) -> Result<(), Error> {
+        if let Err(response) = check_request(request) {
+            response.write_to(&mut stream)?;
+            return Ok(());
+        }
+        let key = request.header("sec-websocket-key");
+        let response = match (request.method.as_str(), request.path.as_str(), key) {
+            ("GET", "/api/monitor", Some(key)) => match Subscription::from_query(&request.query) {
+                Ok(subscription) => match Slot::take(&self.monitors, MAX_MONITORS) {
+                    Some(_monitor) => {
+                        debug!("Starting WebSocket monitor for {:?}", subscription);
+                        stream.write_all(websocket::handshake_response(key).as_bytes())?;
+                        drop(connection);
+                        return monitor::run(stream, subscription, self.stats.clone(), self.destinations.clone());
+                    },
+                    None => Response::error(503, &format!("{} monitors are already open", MAX_MONITORS)),
+                },
+                Err(e) => Response::error(400, &e.to_string()),
+            },
+            ("GET", "/api/monitor", None) => Response::error(400, "Missing Sec-WebSocket-Key header"),
+            _ => Response::error(404, &format!("No WebSocket at {}", request.path)),
+        };
+        response.write_to(&mut stream)?;
+        Ok(())
+    }
+
+    fn config(&self) -> Value {
+        let disabled = &self.control.state().disabled;
+        let mappings = self.destinations.read().unwrap().summaries().into_iter()
+            .map(|summary| json!({
+                "universe": summary.port_address,
+                "artnet": PortAddress::from(summary.port_address).to_string(),
+                "protocol": summary.protocol,
+                "destination": summary.name,
+                "address": summary.address,
+                "enabled": !disabled.contains(&summary.port_address),
+            }))
+            .collect();
+
+        let mut config = self.settings.clone();
+        config["mappings"] = Value::Array(mappings);
+        config
+    }
+
+    // Takes effect straight away, rather than waiting for the reloader to notice the file has changed
+    fn save_file_mappings(&self, mappings: Vec<Value>) -> Result<Value, Error> {
+        let _saving = self.saving.lock().unwrap();
+        let (cfg, issues) = Configuration::write_file_mappings(&self.cli_args, Value::Array(mappings.clone()))?;
+        info!("Mappings were changed from the HTTP API");
+        *self.destinations.write().unwrap() = cfg.destinations;
+        Ok(json!({
+            "mappings": mappings,
+            "warnings": issues.iter().map(|issue| issue.message.clone()).collect::<Vec<String>>(),
+        }))
+    }
+
+    // Every universe received so far, or just one
+    fn universes(&self, only: Option<u16>) -> Value {
+        let now = SystemTime::now();
+        // Copied out so the receive thread isn't kept waiting while they're encoded
+        let universes: Vec<(u16, UniverseStats)> = self.stats.universes.lock().unwrap().iter()
+            .filter(|(&universe, _)| only.is_none() || only == Some(universe))
+            .map(|(&universe, stats)| (universe, stats.clone()))
+            .collect();
+        let universes = universes.into_iter()
+            .map(|(universe, stats)| json!({
+                "universe": universe,
+                "artnet": PortAddress::from(universe).to_string(),
+                "source": stats.source.to_string(),
+                "packets": stats.packets,
+                "received": stats.received.duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or(0),
+                "age_ms": now.duration_since(stats.received).map(|age| age.as_millis() as u64).unwrap_or(0),
+                "data": stats.data,
+                "output": stats.output,
+            }))
+            .collect::<Vec<Value>>();
+
+        match only {
+            Some(_) => Value::Array(universes),
+            None => json!({
+                "received": Stats::get(&self.stats.artnet_received),
+                "out_of_order": Stats::get(&self.stats.artnet_out_of_order),
+                "universes": universes,
+            }),
+        }
+    }
+
+    fn destinations(&self) -> Value {
+        let destinations = self.stats.destinations.lock().unwrap();
+        Value::Array(destinations.iter().map(|(destination, stats)| json!({
+            "destination": destination,
+            "sent": stats.sent,
+            "errors": stats.errors,
+            "last_error": stats.last_error,
+        })).collect())
+    }
+
+    fn control(&self) -> Value {
+        serde_json::to_value(&*self.control.state()).unwrap_or(Value::Null)
+    }
+}
+
+// Any web page can have a browser send requests to the bridge, which has no login to stop them.
+// Browsers say which site a request came from, and won't let a page on another site send a JSON
+// body without permission that the bridge never gives.
+fn check_request(request: &Request) -> Result<(), Response> {
+    if let Some(origin) = request.header("origin") {
+        let same_site = match (origin.split_once("://"), request.header("host")) {
+            (Some((_, origin_host)), Some(host)) => origin_host.eq_ignore_ascii_case(host),
+            _ => false,
+        };
+        if !same_site {
+            return Err(Response::error(403, &format!("Requests from {} aren't allowed", origin)));
+        }
+    }
+
+    let json = request.header("content-type")
+        .and_then(|content_type| content_type.split(';').next())
+        .is_some_and(|media_type| media_type.trim().eq_ignore_ascii_case("application/json"));
+    if matches!(request.method.as_str(), "POST" | "PUT") && !json {
+        return Err(Response::error(415, "Request bodies must be sent with Content-Type: application/json"));
+    }
+    Ok(())
+}
+
+fn parse_body<'a, T: Deserialize<'a>>(body: &'a [u8]) -> Result<T, Error> {
+    serde_json::from_slice(body).map_err(|e| anyhow!("Could not understand request body: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::io::Cursor;
+
+    // Bodies are sent as JSON, as the panel does
+    fn request(method: &str, path: &str, body: &str) -> Request {
+        let headers = match method {
+            "POST" | "PUT" => vec!(("content-type".to_string(), "application/json".to_string())),
+            _ => vec!(),
+        };
+        Request { method: method.to_string(), path: path.to_string(), query: String::new(), headers, body: body.as_bytes().to_vec() }
+    }
+
+    fn with_header(mut request: Request, name: &str, value: &str) -> Request {
+        request.headers.push((name.to_string(), value.to_string()));
+        request
+    }
+
+    fn body(response: &Response) -> Value {
+        serde_json::from_slice(&response.body).unwrap()
+    }
+
+    #[test]
+    fn test_slot() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let first = Slot::take(&count, 2).unwrap();
+        let second = Slot::take(&count, 2).unwrap();
+        assert!(Slot::take(&count, 2).is_none());
+
+        drop(first);
+        let third = Slot::take(&count, 2);
+        assert!(third.is_some());
+        drop((second, third));
+        assert_eq!(count.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn test_read_request() {
+        let mut reader = Cursor::new(&b"POST /api/master?x=1 HTTP/1.1\r\nHost: bridge\r\nContent-length: 15\r\n\r\n{\"master\": 0.5}"[..]);
+        let parsed = read_request(&mut reader).unwrap();
+        assert_eq!((parsed.method.as_str(), parsed.path.as_str(), parsed.query.as_str()), ("POST", "/api/master", "x=1"));
+        assert_eq!((parsed.header("host"), parsed.body.as_slice()), (Some("bridge"), &b"{\"master\": 0.5}"[..]));
+
+        let mut reader = Cursor::new(&b"GET /api/config HTTP/1.0\r\n\r\n"[..]);
+        assert_eq!(read_request(&mut reader).unwrap(), request("GET", "/api/config", ""));
+
+        let bad_cases: Vec<&[u8]> = vec!(
+            b"garbage\r\n\r\n",
+            b"GET / SPDY/3\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: 10\r\n\r\n{}",
+            b"POST / HTTP/1.1\r\nContent-Length: 1000000\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+        );
+        for case in bad_cases {
+            read_request(&mut Cursor::new(case)).expect_err(&format!("Expected {:?} to fail", String::from_utf8_lossy(case)));
+        }
+
+        // Lines that never end are cut off rather than read forever
+        read_request(&mut BufReader::new(io::repeat(b'a'))).expect_err("Expected an endless request line to fail");
+        let endless_header = Cursor::new(&b"GET / HTTP/1.1\r\nX-Padding: "[..]).chain(io::repeat(b'a'));
+        read_request(&mut BufReader::new(endless_header)).expect_err("Expected an endless header to fail");
+    }
+
+    #[test]
+    fn test_handle() {
+        let stats = Arc::new(Stats::default());
+        let control = Arc::new(Control::new(HashMap::new()));
+        let api = Api::new(json!({ "osc_port": null }), UserConfiguration::default(), Arc::new(RwLock::new(Destinations::default())), control.clone(), stats.clone());
+
+        let response = api.handle(&request("GET", "/api/config", ""));
+        assert_eq!(response.status, 200);
+        assert_eq!(body(&response), json!({ "osc_port": null, "mappings": [] }));
+
+        stats.record_frame(17, "10.0.0.5".parse().unwrap(), &[1, 2], SystemTime::now());
+        stats.record_send::<(), &str>("KiNET 10.0.0.1 port 1".to_string(), &Err("unreachable"));
+        let universe = body(&api.handle(&request("GET", "/api/universes/17", "")));
+        assert_eq!((&universe["artnet"], &universe["source"], &universe["data"]), (&json!("0:1:1"), &json!("10.0.0.5"), &json!([1, 2])));
+        assert_eq!(body(&api.handle(&request("GET", "/api/universes", "")))["universes"].as_array().unwrap().len(), 1);
+        assert_eq!(api.handle(&request("GET", "/api/universes/18", "")).status, 404);
+        assert_eq!(body(&api.handle(&request("GET", "/api/destinations", "")))[0]["errors"], json!(1));
+
+        let response = api.handle(&request("POST", "/api/master", r#"{ "master": 0.5 }"#));
+        assert_eq!((response.status, &body(&response)["master"]), (200, &json!(0.5)));
+        api.handle(&request("POST", "/api/blackout", r#"{ "blackout": true }"#));
+        api.handle(&request("POST", "/api/mappings/3", r#"{ "enabled": false }"#));
+        let state = control.state();
+        assert!(state.blackout);
+        assert!(state.disabled.contains(&3));
+        drop(state);
+
+        assert_eq!(api.handle(&request("GET", "/", "")).content_type, "text/html; charset=utf-8");
+        let metrics = api.handle(&request("GET", "/metrics", ""));
+        assert_eq!((metrics.status, metrics.content_type), (200, "text/plain; version=0.0.4"));
+        assert!(String::from_utf8(metrics.body).unwrap().contains("artnet_bridge_blackout 1"));
+        // No config file was given, so there's nothing to edit
+        assert_eq!(api.handle(&request("GET", "/api/config/mappings", "")).status, 400);
+
+        let bad_cases = vec!(
+            (request("POST", "/api/master", r#"{ "master": 2 }"#), 400),
+            (request("POST", "/api/master", r#"{ "level": 1 }"#), 400),
+            (request("POST", "/api/mappings/32768", r#"{ "enabled": true }"#), 400),
+            (request("POST", "/api/blackout", ""), 400),
+            (request("DELETE", "/api/config", ""), 405),
+            (request("POST", "/metrics", ""), 405),
+            (request("PUT", "/api/config/mappings", r#"{ "mappings": "1:10.0.0.1" }"#), 400),
+            (request("GET", "/api/unknown", ""), 404),
+            // Requests that a page on another site could have made
+            (Request { headers: vec!(), ..request("POST", "/api/blackout", r#"{ "blackout": false }"#) }, 415),
+            (with_header(request("DELETE", "/api/blackout", r#"{ "blackout": false }"#), "content-type", "text/plain"), 405),
+            (Request { method: "POST".to_string(), ..with_header(request("GET", "/api/blackout", r#"{ "blackout": false }"#), "content-type", "text/plain") }, 415),
+            (Request { method: "PUT".to_string(), ..with_header(request("GET", "/api/config/mappings", "mappings=1"), "content-type", "application/x-www-form-urlencoded") }, 415),
+            (with_header(with_header(request("POST", "/api/blackout", r#"{ "blackout": false }"#), "host", "10.0.0.2:8080"), "origin", "http://evil.example"), 403),
+            (with_header(request("GET", "/api/config", ""), "origin", "null"), 403),
+        );
+        for (request, status) in bad_cases {
+            assert_eq!(api.handle(&request).status, status, "{:?}", request);
+        }
+        assert!(control.state().blackout);
+
+        // The panel's own requests say they came from the bridge
+        let panel = with_header(with_header(request("POST", "/api/blackout", r#"{ "blackout": false }"#), "host", "10.0.0.2:8080"), "origin", "http://10.0.0.2:8080");
+        assert_eq!(api.handle(&panel).status, 200);
+        assert!(!control.state().blackout);
+        let mut charset = request("POST", "/api/master", r#"{ "master": 1 }"#);
+        charset.headers[0].1 = "Application/JSON; charset=utf-8".to_string();
+        assert_eq!(api.handle(&charset).status, 200);
+    }
+}
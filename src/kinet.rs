@@ -1,5 +1,8 @@
 use serde::{Serialize, Serializer};
 use serde::ser::SerializeTuple;
+use std::collections::HashMap;
+use std::fmt;
+use std::net::SocketAddr;
 
 extern crate bincode;
 
@@ -15,16 +18,20 @@ pub struct Header {
     pub flags: u16,
 }
 
-#[derive(Serialize)]
+// DmxOut has no length field, so the number of channels to send is kept alongside the packet.
+// Supplies expect all 512 unless they're known to accept shorter packets.
 pub struct DmxOut {
     pub header: Header,
     pub timer: i32,
     pub universe: u8,
-    #[serde(serialize_with = "serialize_array512")]
     pub data: [u8; 512],
+    pub length: u16,
 }
 
-#[derive(Serialize)]
+// The start code PortOut packets use for ordinary DMX level data
+pub const DEFAULT_START_CODE: u16 = 0x0FFF;
+
+// Only the first `length` channels of data are sent
 pub struct PortOut {
     pub header: Header,
     pub port: u8,
@@ -32,23 +39,146 @@ pub struct PortOut {
     pub port_out_flags: u16,
     pub length: u16,
     pub start_code: u16,
-    #[serde(serialize_with = "serialize_array512")]
     pub data: [u8; 512],
 }
 
-fn serialize_array512<S, T>(array: &[T; 512], serializer: S) -> Result<S::Ok, S::Error>
-where
-    S: Serializer,
-    T: Serialize,
-{
-    let mut seq = serializer.serialize_tuple(512)?;
-    for element in array.iter() {
-        seq.serialize_element(element)?;
+impl Serialize for DmxOut {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let data = &self.data[..(self.length as usize).min(self.data.len())];
+        let mut packet = serializer.serialize_tuple(3 + data.len())?;
+        packet.serialize_element(&self.header)?;
+        packet.serialize_element(&self.timer)?;
+        packet.serialize_element(&self.universe)?;
+        for channel in data {
+            packet.serialize_element(channel)?;
+        }
+        packet.end()
+    }
+}
+
+impl Serialize for PortOut {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let data = &self.data[..(self.length as usize).min(self.data.len())];
+        let mut packet = serializer.serialize_tuple(6 + data.len())?;
+        packet.serialize_element(&self.header)?;
+        packet.serialize_element(&self.port)?;
+        packet.serialize_element(&self.padding)?;
+        packet.serialize_element(&self.port_out_flags)?;
+        packet.serialize_element(&self.length)?;
+        packet.serialize_element(&self.start_code)?;
+        for channel in data {
+            packet.serialize_element(channel)?;
+        }
+        packet.end()
+    }
+}
+
+pub const KINET_PORT: u16 = 6038;
+
+const MAGIC: i32 = 0x4adc0104;
+const DMX_OUT: u16 = 0x101;
+const PORT_OUT: u16 = 0x108;
+const HEADER_LENGTH: usize = 16;
+const DMX_OUT_HEADER_LENGTH: usize = 21;
+const PORT_OUT_HEADER_LENGTH: usize = 24;
+const MAX_DMX_LENGTH: usize = 512;
+
+// The KiNET packets the bridge understands when receiving from a controller
+pub enum Packet {
+    DmxOut(DmxOut),
+    PortOut(PortOut),
+    Other(u16),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ParseError {
+    NotKinet,
+    TooShort { command: u16, length: usize },
+    PortOutTooLong(usize),
+    PortOutTruncated { expected: usize, received: usize },
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::NotKinet => write!(f, "Not a KiNET packet"),
+            ParseError::TooShort { command, length } =>
+                write!(f, "KiNET packet with command {:#06x} is too short ({} bytes)", command, length),
+            ParseError::PortOutTooLong(length) =>
+                write!(f, "PortOut length {} is longer than {} channels", length, MAX_DMX_LENGTH),
+            ParseError::PortOutTruncated { expected, received } =>
+                write!(f, "PortOut packet has {} channels of data but its length field says {}", received, expected),
+        }
     }
-    return seq.end();
 }
 
-// TODO: create enum and wrap structs for implemented messages, similar to the artnet_protocol crate
+impl std::error::Error for ParseError {}
+
+// Decodes DmxOut and PortOut packets into the same structs the bridge sends. Every field is
+// little-endian. DmxOut has no length field, so it carries however many channels follow the header.
+pub fn parse(buffer: &[u8]) -> Result<Packet, ParseError> {
+    if buffer.len() < HEADER_LENGTH || i32::from_le_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]) != MAGIC {
+        return Err(ParseError::NotKinet);
+    }
+
+    let u16_at = |offset: usize| u16::from_le_bytes([buffer[offset], buffer[offset + 1]]);
+    let header = Header {
+        magic: MAGIC,
+        version: u16_at(4),
+        command: u16_at(6),
+        sequence: i32::from_le_bytes([buffer[8], buffer[9], buffer[10], buffer[11]]),
+        port: buffer[12],
+        padding: buffer[13],
+        flags: u16_at(14),
+    };
+    let too_short = ParseError::TooShort { command: header.command, length: buffer.len() };
+
+    match header.command {
+        DMX_OUT => {
+            if buffer.len() < DMX_OUT_HEADER_LENGTH {
+                return Err(too_short);
+            }
+            let data = &buffer[DMX_OUT_HEADER_LENGTH..];
+            let length = data.len().min(MAX_DMX_LENGTH);
+            let mut packet = DmxOut {
+                header,
+                timer: i32::from_le_bytes([buffer[16], buffer[17], buffer[18], buffer[19]]),
+                universe: buffer[20],
+                data: [0; 512],
+                length: length as u16,
+            };
+            packet.data[..length].copy_from_slice(&data[..length]);
+            Ok(Packet::DmxOut(packet))
+        },
+        PORT_OUT => {
+            if buffer.len() < PORT_OUT_HEADER_LENGTH {
+                return Err(too_short);
+            }
+            let length = u16_at(20) as usize;
+            if length > MAX_DMX_LENGTH {
+                return Err(ParseError::PortOutTooLong(length));
+            }
+            let data = &buffer[PORT_OUT_HEADER_LENGTH..];
+            if data.len() < length {
+                return Err(ParseError::PortOutTruncated { expected: length, received: data.len() });
+            }
+            let mut packet = PortOut {
+                header,
+                port: buffer[16],
+                padding: buffer[17],
+                port_out_flags: u16_at(18),
+                length: length as u16,
+                start_code: u16_at(22),
+                data: [0; 512],
+            };
+            packet.data[..length].copy_from_slice(&data[..length]);
+            Ok(Packet::PortOut(packet))
+        },
+        command => Ok(Packet::Other(command)),
+    }
+}
+
+// Other KiNET messages, not implemented yet
 /*
 pub enum KinetCommand {   
     DiscoverSupplies, // 0x001
@@ -63,10 +193,28 @@ pub enum KinetCommand {
 }
 */
 
+// Numbers the packets sent to each destination (a supply address and output port) so that supplies
+// and packet captures can detect loss and reordering. Sequences start at 1 and skip 0 when they wrap.
+#[derive(Default)]
+pub struct SequenceNumbers {
+    last: HashMap<(SocketAddr, u8), i32>,
+}
+
+impl SequenceNumbers {
+    pub fn next(&mut self, address: SocketAddr, port: u8) -> i32 {
+        let sequence = self.last.entry((address, port)).or_insert(0);
+        *sequence = match sequence.wrapping_add(1) {
+            0 => 1,
+            next => next,
+        };
+        *sequence
+    }
+}
+
 impl Header {
     fn with_command(command_code: u16) -> Self {
         Header {
-            magic: 0x4adc0104,
+            magic: MAGIC,
             version: 0x0001,
             command: command_code,
             sequence: 0x00000000,
@@ -80,10 +228,11 @@ impl Header {
 impl Default for DmxOut {
     fn default() -> Self {
         DmxOut {
-            header: Header::with_command(0x101),
+            header: Header::with_command(DMX_OUT),
             timer: -1,
             universe: 0,
             data: [0; 512],
+            length: 0x0200,
         }
     }
 }
@@ -91,12 +240,12 @@ impl Default for DmxOut {
 impl Default for PortOut {
     fn default() -> Self {
         PortOut {
-            header: Header::with_command(0x108),
+            header: Header::with_command(PORT_OUT),
             port: 1,
             padding: 0,
             port_out_flags: 0x0100,
             length: 0x0200,
-            start_code: 0x0FFF,
+            start_code: DEFAULT_START_CODE,
             data: [0; 512],
         }
     }
@@ -136,6 +285,85 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_serialize_header_fields() {
+        let mut msg = DmxOut::default();
+        msg.header.sequence = 0x01020304;
+        msg.header.flags = 0x0100;
+        msg.timer = 0;
+        let bytes = bincode::serialize(&msg).unwrap_or_default();
+        assert_eq!(bytes[..21], [4, 1, 220, 74, 1, 0, 1, 1, 4, 3, 2, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_serialize_short_packets() {
+        let mut msg = PortOut::default();
+        msg.data[..4].copy_from_slice(&[1, 2, 3, 4]);
+        msg.length = 3;
+        let bytes = bincode::serialize(&msg).unwrap_or_default();
+        assert_eq!(bytes, vec!(4, 1, 220, 74, 1, 0, 8, 1, 0, 0, 0, 0, 0, 0, 0, 0, 1, 0, 0, 1, 3, 0, 255, 15, 1, 2, 3));
+
+        let mut msg = DmxOut::default();
+        msg.data[..4].copy_from_slice(&[1, 2, 3, 4]);
+        msg.length = 2;
+        let bytes = bincode::serialize(&msg).unwrap_or_default();
+        assert_eq!(bytes, vec!(4, 1, 220, 74, 1, 0, 1, 1, 0, 0, 0, 0, 0, 0, 0, 0, 255, 255, 255, 255, 0, 1, 2));
+    }
+
+    #[test]
+    fn test_parse_round_trip() {
+        let mut msg = PortOut { port: 3, length: 4, ..PortOut::default() };
+        msg.header.sequence = 7;
+        msg.data[..4].copy_from_slice(&[1, 2, 3, 4]);
+        match parse(&bincode::serialize(&msg).unwrap()) {
+            Ok(Packet::PortOut(packet)) => {
+                assert_eq!((packet.header.sequence, packet.port, packet.length, packet.start_code), (7, 3, 4, DEFAULT_START_CODE));
+                assert_eq!(packet.data[..5], [1, 2, 3, 4, 0]);
+            },
+            _ => panic!("Expected a PortOut packet"),
+        }
+
+        let mut msg = DmxOut { length: 3, ..DmxOut::default() };
+        msg.data[..3].copy_from_slice(&[9, 8, 7]);
+        match parse(&bincode::serialize(&msg).unwrap()) {
+            Ok(Packet::DmxOut(packet)) => {
+                assert_eq!((packet.timer, packet.length), (-1, 3));
+                assert_eq!(packet.data[..4], [9, 8, 7, 0]);
+            },
+            _ => panic!("Expected a DmxOut packet"),
+        }
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let bytes = bincode::serialize(&PortOut::default()).unwrap();
+        assert_eq!(parse(b"Art-Net\0\0\x50\0\0\0\0\0\0").err(), Some(ParseError::NotKinet));
+        assert_eq!(parse(&bytes[..20]).err(), Some(ParseError::TooShort { command: 0x108, length: 20 }));
+        assert_eq!(parse(&bytes[..100]).err(), Some(ParseError::PortOutTruncated { expected: 512, received: 76 }));
+
+        let mut too_long = bytes.clone();
+        too_long[20..22].copy_from_slice(&513u16.to_le_bytes());
+        assert_eq!(parse(&too_long).err(), Some(ParseError::PortOutTooLong(513)));
+
+        let mut other = bytes;
+        other[6..8].copy_from_slice(&0x0001u16.to_le_bytes());
+        assert!(matches!(parse(&other), Ok(Packet::Other(0x0001))));
+    }
+
+    #[test]
+    fn test_sequence_numbers() {
+        let pds = "10.0.0.1:6038".parse().unwrap();
+        let other_pds = "10.0.0.2:6038".parse().unwrap();
+        let mut sequences = SequenceNumbers::default();
+        assert_eq!(sequences.next(pds, 1), 1);
+        assert_eq!(sequences.next(pds, 1), 2);
+        assert_eq!(sequences.next(pds, 2), 1);
+        assert_eq!(sequences.next(other_pds, 1), 1);
+
+        sequences.last.insert((pds, 1), -1);
+        assert_eq!(sequences.next(pds, 1), 1);
+    }
+
     #[test]
     fn test_serialize_portout_port() {
         let mut msg = PortOut::default();
@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+use std::net::{IpAddr, UdpSocket};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, trace, warn};
+
+use crate::config::KinetSource;
+use crate::kinet;
+use crate::utils::{self, RateLimit};
+
+const MAX_PACKET_SIZE: usize = 2048;
+
+// Receives KiNET from controllers such as Light System Manager or iPlayer on the KiNET socket, and
+// re-sends each configured port as ArtDmx from the Art-Net socket. Runs until the socket fails.
+pub fn spawn(kinet_socket: UdpSocket, artnet_socket: UdpSocket, sources: Vec<KinetSource>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut relay = Relay::new(sources);
+        let mut parse_warning = RateLimit::new(Duration::from_secs(10));
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+
+        loop {
+            let (length, addr) = match kinet_socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Stopped receiving KiNET: {}", e);
+                    return;
+                }
+            };
+
+            let packet = match kinet::parse(&buffer[..length]) {
+                Ok(packet) => packet,
+                Err(e) => {
+                    if parse_warning.ready(Instant::now()) {
+                        warn!("Ignoring packet from {} on the KiNET port: {}", addr, e);
+                    }
+                    continue;
+                }
+            };
+
+            if let Some((source, command)) = relay.route(addr.ip(), &packet) {
+                debug!("Relaying {} to Art-Net node {} universe {}", source, source.artnet_node, source.port_address);
+                if let Err(e) = utils::send_artnet_command(command, &artnet_socket, &source.artnet_socket_addr) {
+                    error!("Could not relay {} to {}: {}", source, source.artnet_node, e);
+                }
+            }
+        }
+    })
+}
+
+struct Relay {
+    sources: Vec<KinetSource>,
+    sequences: HashMap<usize, u8>,
+}
+
+impl Relay {
+    fn new(sources: Vec<KinetSource>) -> Self {
+        Relay { sources, sequences: HashMap::new() }
+    }
+
+    // Finds where a packet should go, preferring an input for its controller over one for any
+    // controller, and builds the ArtDmx packet to send there
+    fn route(&mut self, controller: IpAddr, packet: &kinet::Packet) -> Option<(&KinetSource, artnet_protocol::ArtCommand)> {
+        let (port, data) = match packet {
+            kinet::Packet::DmxOut(dmx_out) => (0, &dmx_out.data[..dmx_out.length as usize]),
+            // Only ordinary level data can be sent as ArtDmx
+            kinet::Packet::PortOut(port_out) if port_out.start_code == kinet::DEFAULT_START_CODE || port_out.start_code == 0 =>
+                (port_out.port, &port_out.data[..port_out.length as usize]),
+            kinet::Packet::PortOut(port_out) => {
+                trace!("Not relaying KiNET port {} data with start code {:#06x}", port_out.port, port_out.start_code);
+                return None;
+            },
+            kinet::Packet::Other(command) => {
+                trace!("Received unhandled KiNET command {:#06x} from {}", command, controller);
+                return None;
+            }
+        };
+
+        let index = self.sources.iter().position(|source| source.kinet_source.is_some() && source.matches(controller, port))
+            .or_else(|| self.sources.iter().position(|source| source.matches(controller, port)));
+        let index = match index {
+            Some(index) => index,
+            None => {
+                trace!("No Art-Net universe configured for KiNET port {} from {}", port, controller);
+                return None;
+            }
+        };
+
+        // Art-Net sequence numbers run from 1 to 255, as 0 turns sequencing off
+        let sequence = self.sequences.entry(index).or_insert(0);
+        *sequence = sequence.wrapping_add(1).max(1);
+
+        let source = &self.sources[index];
+        Some((source, utils::dmx_output(source.port_address, *sequence, port, data)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use artnet_protocol::ArtCommand;
+    use crate::artnet::PortAddress;
+
+    fn source(kinet_source: Option<&str>, kinet_port: u8, universe: u16) -> KinetSource {
+        KinetSource {
+            kinet_source: kinet_source.map(|source| source.parse().unwrap()),
+            kinet_port,
+            port_address: PortAddress::from_flat(universe).unwrap(),
+            artnet_node: "127.0.0.1".to_string(),
+            artnet_socket_addr: "127.0.0.1:6454".parse().unwrap(),
+        }
+    }
+
+    fn universe_of(relayed: Option<(&KinetSource, ArtCommand)>) -> Option<(u16, u8, Vec<u8>)> {
+        match relayed {
+            Some((_, ArtCommand::Output(output))) => Some((output.subnet, output.sequence, output.data)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_relay() {
+        let mut relay = Relay::new(vec!(source(None, 1, 10), source(Some("10.0.0.9"), 1, 11), source(None, 0, 12)));
+        let controller = "10.0.0.5".parse().unwrap();
+        let other_controller = "10.0.0.9".parse().unwrap();
+
+        let mut port_out = kinet::PortOut { length: 2, ..kinet::PortOut::default() };
+        port_out.data[..2].copy_from_slice(&[5, 6]);
+        let port_out = kinet::Packet::PortOut(port_out);
+        assert_eq!(universe_of(relay.route(controller, &port_out)), Some((10, 1, vec!(5, 6))));
+        assert_eq!(universe_of(relay.route(controller, &port_out)), Some((10, 2, vec!(5, 6))));
+        assert_eq!(universe_of(relay.route(other_controller, &port_out)), Some((11, 1, vec!(5, 6))));
+
+        let dmx_out = kinet::DmxOut { length: 3, ..kinet::DmxOut::default() };
+        let dmx_out = kinet::Packet::DmxOut(dmx_out);
+        assert_eq!(universe_of(relay.route(controller, &dmx_out)), Some((12, 1, vec!(0, 0, 0, 0))));
+
+        // Unconfigured ports and alternate start codes aren't relayed
+        let port_out = kinet::PortOut { port: 2, ..kinet::PortOut::default() };
+        assert!(relay.route(controller, &kinet::Packet::PortOut(port_out)).is_none());
+        let port_out = kinet::PortOut { start_code: 0xcc, ..kinet::PortOut::default() };
+        assert!(relay.route(controller, &kinet::Packet::PortOut(port_out)).is_none());
+    }
+}
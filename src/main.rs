@@ -1,32 +1,51 @@
 use structopt::StructOpt;
-use std::net::{Ipv4Addr, UdpSocket};
+use std::collections::HashMap;
+use std::mem;
+use std::net::{TcpListener, UdpSocket};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant, SystemTime};
 use artnet_protocol::{ArtCommand, PollReply};
-use std::str::FromStr;
 
-use log::{error, info, debug, trace};
+use log::{error, warn, info, debug, trace};
 use anyhow::Error;
 
 extern crate pretty_env_logger;
 extern crate serde_json;
 extern crate bincode;
 
+mod artnet;
+mod commands;
 mod config;
+mod control;
+mod ddp;
+mod http;
 mod kinet;
+mod kinet_input;
+mod metrics;
+mod monitor;
+mod opc;
+mod osc;
+mod pipeline;
+mod receiver;
+mod reload;
+mod resolver;
+mod sacn;
+mod sequence;
+mod stats;
 mod utils;
+mod websocket;
 
 fn main() -> Result<(), Error> {
 
     // Load configuration from command line
     let cli_args = config::UserConfiguration::from_args();
+    let cfg = config::Configuration::load(&cli_args)?;
 
-    let mut file_args = config::UserConfiguration::default();
-    if cli_args.config_file.is_some() {
-        let file_path = cli_args.config_file.clone().unwrap();
-        file_args = config::UserConfiguration::from_file(file_path)?;
+    if let Some(command) = &cli_args.command {
+        let valid = commands::run(command, &cfg);
+        std::process::exit(if valid { 0 } else { 1 });
     }
 
-    let cfg = config::Configuration::from_user_configs(cli_args, file_args)?;
-
     pretty_env_logger::formatted_timed_builder()
         .filter(None, cfg.get_log_level().unwrap().to_level_filter())
         .init();
@@ -39,36 +58,331 @@ fn main() -> Result<(), Error> {
     short_name.copy_from_slice(&default_short_name.as_bytes()[..18]);
     long_name[..26].copy_from_slice(&default_long_name.as_bytes()[..]);
 
-    info!("Listening for Art-Net packets on {}", cfg.artnet_receive_ip);
-    info!("Transmitting KiNET on {}", cfg.kinet_send_ip);
+    info!("Listening for Art-Net packets on {} ({})", cfg.artnet_receive_ip, cfg.artnet_receive_addr);
+    info!("Transmitting KiNET on {} ({})", cfg.kinet_send_ip, cfg.kinet_send_addr);
     info!("Mapping Art-Net to the following KiNET destinations:");
-    for mapping in cfg.kinet_destinations.values() {
+    for mapping in cfg.destinations.kinet.values() {
         info!("{:?}", mapping);
     }
+    if !cfg.destinations.opc.is_empty() {
+        info!("Mapping Art-Net to the following OPC destinations:");
+        for mapping in cfg.destinations.opc.values() {
+            info!("{:?}", mapping);
+        }
+    }
+    if !cfg.destinations.sacn.is_empty() {
+        info!("Mapping Art-Net to the following sACN destinations:");
+        for mapping in cfg.destinations.sacn.values() {
+            info!("{:?}", mapping);
+        }
+    }
+    if !cfg.destinations.artnet.is_empty() {
+        info!("Relaying Art-Net to the following Art-Net destinations:");
+        for mapping in cfg.destinations.artnet.values() {
+            info!("{:?}", mapping);
+        }
+    }
+    if !cfg.destinations.ddp.is_empty() {
+        info!("Mapping Art-Net to the following DDP destinations:");
+        for mapping in cfg.destinations.ddp.values() {
+            info!("{:?}", mapping);
+        }
+    }
+    if !cfg.kinet_inputs.is_empty() {
+        info!("Relaying KiNET from controllers to the following Art-Net destinations:");
+        for input in &cfg.kinet_inputs {
+            info!("{:?}", input);
+        }
+    }
+    for issue in cfg.validate() {
+        warn!("{}", issue);
+    }
         
     let artnet_socket = 
-        UdpSocket::bind((&cfg.artnet_receive_ip[..], 6454))
+        UdpSocket::bind((cfg.artnet_receive_addr, artnet::ARTNET_PORT))
         .expect("Could not bind to Art-Net address.");
     let kinet_socket = 
-        UdpSocket::bind((&cfg.kinet_send_ip[..], 6038))
+        UdpSocket::bind((cfg.kinet_send_addr, kinet::KINET_PORT))
         .expect("Could not bind to KiNET address.");
-    
+    // sACN is sent from the same interface as KiNET, from any free port
+    let sacn_socket =
+        UdpSocket::bind((cfg.kinet_send_addr, 0))
+        .expect("Could not bind to sACN address.");
+    let ddp_socket =
+        UdpSocket::bind((cfg.kinet_send_addr, 0))
+        .expect("Could not bind to DDP address.");
+
+    // Art-Net destinations and nodes fed from KiNET inputs can be given as a broadcast address
+    artnet_socket.set_broadcast(true)?;
+
+    // KiNET from controllers arrives on the KiNET socket, and goes out as Art-Net from the Art-Net socket
+    if !cfg.kinet_inputs.is_empty() {
+        kinet_input::spawn(kinet_socket.try_clone()?, artnet_socket.try_clone()?, cfg.kinet_inputs.clone());
+    }
+
+    let reloader = reload::Reloader::new(cli_args.clone(), &cfg);
+    let http_settings = http::Api::settings(&cfg);
+    let destinations = Arc::new(RwLock::new(cfg.destinations));
+    resolver::spawn(destinations.clone());
+    reloader.spawn(destinations.clone())?;
+
+    let stats = Arc::new(stats::Stats::default());
+    let control = Arc::new(control::Control::new(cfg.scenes.clone()));
+    if let Some(osc_port) = cfg.osc_port {
+        let osc_socket =
+            UdpSocket::bind((cfg.artnet_receive_addr, osc_port))
+            .expect("Could not bind to OSC port.");
+        info!("Listening for OSC control messages on port {}", osc_port);
+        osc::spawn(osc_socket, control.clone(), stats.clone());
+    }
+    if let Some(http_port) = cfg.http_port {
+        let http_listener =
+            TcpListener::bind((cfg.http_addr, http_port))
+            .expect("Could not bind to HTTP port.");
+        info!("Serving the HTTP API and panel on {} port {}", cfg.http_addr, http_port);
+        http::spawn(http_listener, http::Api::new(http_settings, cli_args, destinations.clone(), control.clone(), stats.clone()));
+    }
+
+    // Each output is sent from its own thread. Destinations are looked up as frames are sent, so
+    // reloaded mappings apply straight away.
+    let kinet_frames = {
+        let destinations = destinations.clone();
+        let (kinet_flags, kinet_timer) = (cfg.kinet_flags, cfg.kinet_timer);
+        let mut kinet_sequences = kinet::SequenceNumbers::default();
+        let stats = stats.clone();
+        pipeline::spawn("KiNET output", stats.clone(), move |output| {
+            // Cloned so the lock isn't held while sending
+            let destination = destinations.read().unwrap().kinet.get(&output.port_address.value()).cloned();
+            if let Some(destination) = destination {
+                let data = &output.data;
+                let length = data.len();
+
+                // Send the configured number of channels, or as many as the console sent
+                let channels = destination.kinet_channels.map_or(length, |channels| channels as usize);
+                let copied = length.min(channels);
+
+                if !config::is_resolved(destination.kinet_socket_addr) {
+                    debug!("Not sending to {:?}, its address hasn't been resolved yet", destination.kinet_address);
+                } else if destination.kinet_port == 0 && output.start_code != 0 {
+                    debug!("Not sending start code {:#04x} data to {:?}, KiNET v1 DmxOut can only carry level data",
+                        output.start_code, destination.kinet_address);
+                } else if destination.kinet_port == 0 {
+                    let mut dmx_out_msg = kinet::DmxOut::default();
+                    dmx_out_msg.header.sequence = kinet_sequences.next(destination.kinet_socket_addr, 0);
+                    dmx_out_msg.header.flags = kinet_flags;
+                    dmx_out_msg.timer = kinet_timer;
+                    dmx_out_msg.data[..copied].copy_from_slice(&data[..copied]);
+                    if destination.kinet_trim_dmx_out {
+                        dmx_out_msg.length = channels as u16;
+                    }
+                    match bincode::serialize(&dmx_out_msg) {
+                        Err(e) => { error!("{:?}", e); },
+                        Ok(bytes) => {
+                            debug!("Sending KiNET DmxOut packet to {:?}", destination.kinet_address);
+                            trace!("{:?}", bytes);
+                            let result = kinet_socket.send_to(&bytes, &destination.kinet_socket_addr);
+                            if let Err(e) = &result {
+                                error!("{:?}", e);
+                            }
+                            stats.record_send(format!("KiNET {}", destination), &result);
+                        }
+                    }
+                } else {
+                    let mut port_out_msg = kinet::PortOut::default();
+                    port_out_msg.header.sequence = kinet_sequences.next(destination.kinet_socket_addr, destination.kinet_port);
+                    port_out_msg.header.flags = kinet_flags;
+                    port_out_msg.port = destination.kinet_port;
+                    port_out_msg.length = channels as u16;
+                    port_out_msg.start_code = match output.start_code {
+                        0 => destination.kinet_start_code.unwrap_or(kinet::DEFAULT_START_CODE),
+                        start_code => start_code as u16,
+                    };
+                    port_out_msg.data[..copied].copy_from_slice(&data[..copied]);
+                    match bincode::serialize(&port_out_msg) {
+                        Err(e) => { error!("{:?}", e); },
+                        Ok(bytes) => {
+                            debug!("Sending KiNET PortOut packet to {:?} port {:?}", destination.kinet_address, destination.kinet_port);
+                            trace!("{:?}", bytes);
+                            
+                            let result = kinet_socket.send_to(&bytes, &destination.kinet_socket_addr);
+                            if let Err(e) = &result {
+                                error!("{:?}", e);
+                            }
+                            stats.record_send(format!("KiNET {}", destination), &result);
+                        }
+                    }
+                }
+            }
+        })
+    };
+
+    // sACN carries alternate start codes the same way as level data
+    let sacn_frames = {
+        let destinations = destinations.clone();
+        let mut sacn_output = sacn::SacnOutput::new(sacn_socket, &cfg.sacn_source_name, cfg.sacn_cid, cfg.sacn_priority);
+        let stats = stats.clone();
+        pipeline::spawn("sACN output", stats.clone(), move |output| {
+            // Cloned so the lock isn't held while sending
+            let destination = destinations.read().unwrap().sacn.get(&output.port_address.value()).cloned();
+            if let Some(destination) = destination {
+                if !config::is_resolved(destination.sacn_socket_addr) {
+                    debug!("Not sending {}, its address hasn't been resolved yet", destination);
+                } else {
+                    debug!("Sending {}", destination);
+                    let result = sacn_output.send(&destination, output.start_code, &output.data);
+                    if let Err(e) = &result {
+                        error!("Could not send {}: {}", destination, e);
+                    }
+                    stats.record_send(destination.to_string(), &result);
+                }
+            }
+        })
+    };
+
+    // Relayed from the Art-Net socket, so nodes see the standard source port. The console's
+    // sequence numbers are kept, as packets have already been put in order.
+    let artnet_frames = {
+        let destinations = destinations.clone();
+        let artnet_socket = artnet_socket.try_clone()?;
+        let stats = stats.clone();
+        pipeline::spawn("Art-Net output", stats.clone(), move |output| {
+            // Cloned so the lock isn't held while sending
+            let destination = destinations.read().unwrap().artnet.get(&output.port_address.value()).cloned();
+            if let Some(destination) = destination {
+                if !config::is_resolved(destination.artnet_socket_addr) {
+                    debug!("Not relaying to {}, its address hasn't been resolved yet", destination);
+                } else if output.start_code != 0 {
+                    debug!("Not relaying start code {:#04x} data to {}", output.start_code, destination);
+                } else {
+                    debug!("Relaying to {}", destination);
+                    let command = utils::dmx_output(destination.artnet_output_universe, output.sequence, output.physical, &output.data);
+                    let result = utils::send_artnet_command(command, &artnet_socket, &destination.artnet_socket_addr);
+                    if let Err(e) = &result {
+                        error!("Could not relay to {}: {}", destination, e);
+                    }
+                    stats.record_send(destination.to_string(), &result);
+                }
+            }
+        })
+    };
+
+    // Like OPC, DDP only carries pixel levels
+    let ddp_frames = {
+        let destinations = destinations.clone();
+        let mut ddp_output = ddp::DdpOutput::new(ddp_socket);
+        let stats = stats.clone();
+        pipeline::spawn("DDP output", stats.clone(), move |output| {
+            // Cloned so the lock isn't held while sending
+            let destination = {
+                let destinations = destinations.read().unwrap();
+                destinations.ddp.get(&output.port_address.value())
+                    .map(|destination| (destination.clone(), ddp::universes_for_device(destination, &destinations.ddp)))
+            };
+            if let Some((destination, universes)) = destination {
+                if !config::is_resolved(destination.ddp_socket_addr) {
+                    debug!("Not sending to {}, its address hasn't been resolved yet", destination);
+                } else if output.start_code == 0 {
+                    let result = ddp_output.send(&destination, &output.data, universes);
+                    match &result {
+                        Err(e) => { error!("Could not send to {}: {}", destination, e); },
+                        Ok(0) => {},
+                        Ok(packets) => { debug!("Sent a frame of {} packets to {}", packets, destination); }
+                    }
+                    // Nothing is counted while the frame waits for more universes
+                    if !matches!(result, Ok(0)) {
+                        stats.record_send(destination.to_string(), &result);
+                    }
+                }
+            }
+        })
+    };
+
+    // Frames are adjusted by the live controls, then handed to each output they're mapped to. Each
+    // OPC server gets its own output thread, started when a frame is first mapped to it, as a TCP
+    // server can hold up sending for much longer than a UDP socket.
+    let frames = {
+        let (destinations, control) = (destinations.clone(), control.clone());
+        let opc_destinations = destinations.clone();
+        let mut opc_servers = HashMap::new();
+        let mut levels = Vec::with_capacity(artnet::MAX_DMX_LENGTH);
+        let stats = stats.clone();
+        pipeline::spawn("Processing", stats.clone(), move |mut output| {
+            let port_address = output.port_address;
+
+            // Master, blackout and held channels only change level data, but a disabled
+            // universe isn't sent anywhere
+            let data = {
+                let control = control.state();
+                match output.start_code {
+                    0 => control.apply(port_address.value(), &output.data, &mut levels).map(<[u8]>::to_vec),
+                    _ if control.disabled.contains(&port_address.value()) => None,
+                    _ => Some(mem::take(&mut output.data)),
+                }
+            };
+            output.data = match data {
+                Some(data) => data,
+                None => {
+                    debug!("Mappings for {} are disabled", port_address);
+                    return;
+                }
+            };
+            if output.start_code == 0 {
+                stats.record_output(port_address.value(), &output.data);
+            }
+
+            let destinations = destinations.read().unwrap();
+            let universe = port_address.value();
+            let opc_frames = destinations.opc.get(&universe).map(|destination| {
+                &*opc_servers.entry(destination.opc_address.clone()).or_insert_with(|| {
+                    opc::spawn(destination.opc_address.clone(), opc_destinations.clone(), stats.clone())
+                })
+            });
+            let outputs = [
+                destinations.kinet.get(&universe).map(|_| &kinet_frames),
+                opc_frames,
+                destinations.sacn.get(&universe).map(|_| &sacn_frames),
+                destinations.artnet.get(&universe).map(|_| &artnet_frames),
+                destinations.ddp.get(&universe).map(|_| &ddp_frames),
+            ];
+            if outputs.iter().all(Option::is_none) {
+                debug!("No destination specified for this Art-Net output");
+                stats.record_unmapped(universe);
+            }
+            for frames in outputs.iter().flatten() {
+                frames.put(output.clone());
+            }
+        })
+    };
+
+    // Packets are received here, and only checked and counted before being handed on
+    let mut sequence_tracker = sequence::SequenceTracker::default();
+    let mut out_of_order_warning = utils::RateLimit::new(Duration::from_secs(10));
+    let mut receiver = receiver::Receiver::new(artnet_socket.try_clone()?, stats.clone());
     loop {
-        let mut buffer = [0u8; 1024];
-        let (length, addr) = artnet_socket.recv_from(&mut buffer)?;
-        let command = ArtCommand::from_buffer(&buffer[..length])?;
-        
-        match command {
-            ArtCommand::Poll(poll) => {
+        let (packet, addr) = match receiver.receive()? {
+            Some(received) => received,
+            None => continue,
+        };
+
+        match packet {
+            artnet::Packet::Poll(poll) => {
                 debug!("Received Art-Net poll command {:?}", poll);
+
+                // When bound to all interfaces, report the address the poller can reach us on
+                let reply_address = if cfg.artnet_receive_addr.is_unspecified() {
+                    utils::local_address_for_peer(&addr).unwrap_or(cfg.artnet_receive_addr)
+                } else {
+                    cfg.artnet_receive_addr
+                };
                 
                 let command = ArtCommand::PollReply(
                     Box::new( 
                         PollReply {
-                            address: Ipv4Addr::from_str(&cfg.artnet_receive_ip)?,
-                            port: 6454,
+                            address: reply_address,
+                            port: artnet::ARTNET_PORT,
                             short_name: short_name,
                             long_name: long_name,
+                            bind_ip: reply_address.octets(),
                             ..utils::default_poll_reply()
                         }
                     )
@@ -78,65 +392,40 @@ fn main() -> Result<(), Error> {
                     Ok(()) => {}
                 }
             },
-            ArtCommand::PollReply(_reply) => {
+            artnet::Packet::PollReply => {
             },
-            ArtCommand::Output(output) => {
-                // artnet_protocol 0.2.0 seems to parse the length field with the wrong endianness, reverse it
-                let length = match output.length.swap_bytes() {
-                    0..=512 => output.length.swap_bytes(),
-                    _ => 512
-                };
+            artnet::Packet::Dmx(output) => {
+                stats::Stats::increment(&stats.artnet_received);
+                let length = output.data.len();
+                let port_address = output.port_address;
+                stats.record_source(port_address.value(), addr.ip());
 
-                let artnet_universe = (output.subnet & 0x0F) as u8;
-                let artnet_subnet   = ((output.subnet & 0xF0) >> 4) as u8;
-                let artnet_network  = ((output.subnet & 0x7F00) >> 8) as u8;
+                // Alternate start code data may be numbered separately from level data, so only
+                // level data is checked for ordering
+                let now = Instant::now();
+                if output.start_code == 0 && !sequence_tracker.accept(addr.ip(), port_address, output.sequence, now) {
+                    stats::Stats::increment(&stats.artnet_out_of_order);
+                    debug!("Dropping out of order Art-Net output for {} from {} (sequence {})",
+                        port_address, addr.ip(), output.sequence);
+                    if out_of_order_warning.ready(now) {
+                        warn!("Dropped {} out of order Art-Net packets so far", stats::Stats::get(&stats.artnet_out_of_order));
+                    }
+                    continue;
+                }
 
-                debug!("Received Art-Net output command for net/subnet/universe {:?}:{:?}:{:?} with length {:?}", 
-                    artnet_network, artnet_subnet, artnet_universe, length);
+                debug!("Received Art-Net output command for net/subnet/universe {} ({}) with length {:?} and start code {:#04x}", 
+                    port_address, port_address.value(), length, output.start_code);
                 trace!("{:?}", output);
-
-                match cfg.kinet_destinations.get(&output.subnet) {
-                    None => {
-                        debug!("No KiNET destination specified for this Art-Net output");
-                    },
-                    Some(destination) => {
-                        if destination.kinet_port == 0 {
-                            let mut dmx_out_msg = kinet::DmxOut::default();
-                            dmx_out_msg.data[..length as usize].copy_from_slice(&output.data[..length as usize]);
-                            match bincode::serialize(&dmx_out_msg) {
-                                Err(e) => { error!("{:?}", e); },
-                                Ok(bytes) => {
-                                    debug!("Sending KiNET DmxOut packet to {:?}", destination.kinet_address);
-                                    trace!("{:?}", bytes);
-                                    match kinet_socket.send_to(&bytes, &destination.kinet_socket_addr) {
-                                        Err(e) => { error!("{:?}", e); },
-                                        Ok(_count) => {}
-                                    }
-                                }
-                            }
-                        } else {
-                            let mut port_out_msg = kinet::PortOut::default();
-                            port_out_msg.port = destination.kinet_port;
-                            port_out_msg.data[..length as usize].copy_from_slice(&output.data[..length as usize]);
-                            match bincode::serialize(&port_out_msg) {
-                                Err(e) => { error!("{:?}", e); },
-                                Ok(bytes) => {
-                                    debug!("Sending KiNET PortOut packet to {:?} port {:?}", destination.kinet_address, destination.kinet_port);
-                                    trace!("{:?}", bytes);
-                                    
-                                    match kinet_socket.send_to(&bytes, &destination.kinet_socket_addr) {
-                                        Err(e) => { error!("{:?}", e); },
-                                        Ok(_count) => {}
-                                    }
-                                }
-                            }
-                        }
-                    }
+                if output.start_code == 0 {
+                    stats.record_frame(port_address.value(), addr.ip(), output.data, SystemTime::now());
                 }
+                frames.put(pipeline::Frame::from(&output));
+            },
+            artnet::Packet::Sync => {
+                trace!("Received Art-Net sync command");
             },
-            _ => {
-                debug!("Received unhandled Art-Net command");
-                trace!("{:?}", command);
+            artnet::Packet::Other(opcode) => {
+                debug!("Received unhandled Art-Net command {:#06x}", opcode);
             }
         }
     }
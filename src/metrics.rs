@@ -0,0 +1,134 @@
+use std::fmt::Write;
+use std::time::SystemTime;
+
+use crate::control::ControlState;
+use crate::stats::Stats;
+
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+// A frame rate is reported as 0 once a universe has stopped, rather than holding its last value
+const STOPPED_AFTER_SECONDS: f64 = 2.0;
+
+// Writes the bridge's counters in the Prometheus text exposition format
+pub fn render(stats: &Stats, control: &ControlState, now: SystemTime) -> String {
+    let mut metrics = Metrics::default();
+
+    metrics.header("artnet_bridge_artnet_packets_total", "counter", "ArtDmx packets received, by universe and source");
+    for ((universe, source), packets) in stats.artnet_sources.lock().unwrap().iter() {
+        metrics.sample("artnet_bridge_artnet_packets_total", &[("universe", &universe.to_string()), ("source", &source.to_string())], *packets as f64);
+    }
+    metrics.header("artnet_bridge_artnet_untracked_packets_total", "counter", "ArtDmx packets from sources beyond the ones counted separately");
+    metrics.sample("artnet_bridge_artnet_untracked_packets_total", &[], Stats::get(&stats.artnet_untracked_sources) as f64);
+    metrics.header("artnet_bridge_artnet_out_of_order_total", "counter", "ArtDmx packets dropped for arriving out of order");
+    metrics.sample("artnet_bridge_artnet_out_of_order_total", &[], Stats::get(&stats.artnet_out_of_order) as f64);
+    metrics.header("artnet_bridge_artnet_parse_errors_total", "counter", "Packets on the Art-Net port that could not be parsed");
+    metrics.sample("artnet_bridge_artnet_parse_errors_total", &[], Stats::get(&stats.artnet_parse_errors) as f64);
+
+    metrics.header("artnet_bridge_unmapped_packets_total", "counter", "ArtDmx packets for universes with no destination");
+    for (universe, packets) in stats.unmapped.lock().unwrap().iter() {
+        metrics.sample("artnet_bridge_unmapped_packets_total", &[("universe", &universe.to_string())], *packets as f64);
+    }
+
+    {
+        let universes = stats.universes.lock().unwrap();
+        let age = |received: SystemTime| now.duration_since(received).unwrap_or_default().as_secs_f64();
+        metrics.header("artnet_bridge_universe_fps", "gauge", "Frames of level data received per second, by universe");
+        for (universe, universe_stats) in universes.iter() {
+            let fps = if age(universe_stats.received) < STOPPED_AFTER_SECONDS { universe_stats.frame_rate.fps } else { 0.0 };
+            metrics.sample("artnet_bridge_universe_fps", &[("universe", &universe.to_string())], fps);
+        }
+        metrics.header("artnet_bridge_universe_last_seen_seconds", "gauge", "Seconds since level data was last received, by universe");
+        for (universe, universe_stats) in universes.iter() {
+            metrics.sample("artnet_bridge_universe_last_seen_seconds", &[("universe", &universe.to_string())], age(universe_stats.received));
+        }
+    }
+
+    {
+        let destinations = stats.destinations.lock().unwrap();
+        metrics.header("artnet_bridge_destination_packets_total", "counter", "Packets sent, by destination");
+        for (destination, destination_stats) in destinations.iter() {
+            metrics.sample("artnet_bridge_destination_packets_total", &[("destination", destination)], destination_stats.sent as f64);
+        }
+        metrics.header("artnet_bridge_destination_errors_total", "counter", "Packets that could not be sent, by destination");
+        for (destination, destination_stats) in destinations.iter() {
+            metrics.sample("artnet_bridge_destination_errors_total", &[("destination", destination)], destination_stats.errors as f64);
+        }
+    }
+
+    metrics.header("artnet_bridge_frames_superseded_total", "counter", "Frames replaced by a newer one before a stage got to them, by stage");
+    for (stage, frames) in stats.superseded.lock().unwrap().iter() {
+        metrics.sample("artnet_bridge_frames_superseded_total", &[("stage", stage)], *frames as f64);
+    }
+
+    metrics.header("artnet_bridge_master", "gauge", "Master level from 0 to 1");
+    metrics.sample("artnet_bridge_master", &[], control.master as f64);
+    metrics.header("artnet_bridge_blackout", "gauge", "1 while blackout is on");
+    metrics.sample("artnet_bridge_blackout", &[], if control.blackout { 1.0 } else { 0.0 });
+    metrics.header("artnet_bridge_disabled_universes", "gauge", "Universes whose mappings are turned off");
+    metrics.sample("artnet_bridge_disabled_universes", &[], control.disabled.len() as f64);
+
+    metrics.text
+}
+
+#[derive(Default)]
+struct Metrics {
+    text: String,
+}
+
+impl Metrics {
+    fn header(&mut self, name: &str, kind: &str, help: &str) {
+        let _ = writeln!(self.text, "# HELP {} {}", name, help);
+        let _ = writeln!(self.text, "# TYPE {} {}", name, kind);
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: f64) {
+        let labels: Vec<String> = labels.iter()
+            .map(|(label, value)| format!("{}=\"{}\"", label, escape(value)))
+            .collect();
+        match labels.is_empty() {
+            true => { let _ = writeln!(self.text, "{} {}", name, value); },
+            false => { let _ = writeln!(self.text, "{}{{{}}} {}", name, labels.join(","), value); },
+        }
+    }
+}
+
+fn escape(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"").replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[test]
+    fn test_render() {
+        let stats = Stats::default();
+        let now = SystemTime::now();
+        let source = "10.0.0.5".parse().unwrap();
+        stats.record_source(1, source);
+        stats.record_source(1, source);
+        stats.record_frame(1, source, &[0], now - Duration::from_secs(3));
+        stats.record_unmapped(2);
+        stats.record_superseded("KiNET output");
+        stats.record_send::<(), &str>("KiNET 10.0.0.1 port 1".to_string(), &Err("unreachable"));
+        stats.record_send::<(), &str>("DDP device \"pixels\"".to_string(), &Ok(()));
+
+        let metrics = render(&stats, &ControlState { master: 0.5, ..ControlState::default() }, now);
+        for line in &[
+            "# TYPE artnet_bridge_artnet_packets_total counter",
+            "artnet_bridge_artnet_packets_total{universe=\"1\",source=\"10.0.0.5\"} 2",
+            "artnet_bridge_artnet_parse_errors_total 0",
+            "artnet_bridge_unmapped_packets_total{universe=\"2\"} 1",
+            "artnet_bridge_universe_fps{universe=\"1\"} 0",
+            "artnet_bridge_universe_last_seen_seconds{universe=\"1\"} 3",
+            "artnet_bridge_destination_errors_total{destination=\"KiNET 10.0.0.1 port 1\"} 1",
+            "artnet_bridge_destination_packets_total{destination=\"DDP device \\\"pixels\\\"\"} 1",
+            "artnet_bridge_frames_superseded_total{stage=\"KiNET output\"} 1",
+            "artnet_bridge_master 0.5",
+            "artnet_bridge_blackout 0",
+        ] {
+            assert!(metrics.lines().any(|metric| metric == *line), "Expected {} in:\n{}", line, metrics);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::{BTreeSet, HashMap};
+use std::io::BufReader;
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use anyhow::{anyhow, Error};
+use log::debug;
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::artnet::PortAddress;
+use crate::config::{Destinations, UniverseSpec};
+use crate::stats::{Stats, UniverseStats};
+use crate::websocket::{self, Message};
+
+const DEFAULT_FPS: u8 = 10;
+// The fastest DMX refresh rate, so there's no point going faster
+const MAX_FPS: u8 = 44;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Format {
+    Json,
+    Binary,
+}
+
+// What a monitor client wants to see. An empty set of universes means all of them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Subscription {
+    pub universes: BTreeSet<u16>,
+    pub format: Format,
+    pub fps: u8,
+}
+
+// Sent by clients as text messages to change their subscription. Universes are written as in a
+// config file, as a number or in the net:subnet:universe form, the same as in the query string.
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct SubscriptionUpdate {
+    universes: Option<Vec<UniverseSpec>>,
+    format: Option<Format>,
+    fps: Option<u8>,
+}
+
+impl Default for Subscription {
+    fn default() -> Self {
+        Subscription { universes: BTreeSet::new(), format: Format::Json, fps: DEFAULT_FPS }
+    }
+}
+
+impl Subscription {
+    // From a query string such as universes=1,2&format=binary&fps=20
+    pub fn from_query(query: &str) -> Result<Self, Error> {
+        let mut subscription = Subscription::default();
+        for parameter in query.split('&').filter(|parameter| !parameter.is_empty()) {
+            let (name, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            match name {
+                "universes" => {
+                    subscription.universes = value.split(',')
+                        .filter(|universe| !universe.is_empty())
+                        .map(|universe| UniverseSpec::Text(universe.to_string()).port_address().map(PortAddress::value))
+                        .collect::<Result<_, _>>()?;
+                },
+                "format" => subscription.format = match value {
+                    "json" => Format::Json,
+                    "binary" => Format::Binary,
+                    _ => return Err(anyhow!("Unknown format {} (must be json or binary)", value)),
+                },
+                "fps" => subscription.set_fps(value.parse().map_err(|_| anyhow!("Could not understand fps {}", value))?)?,
+                _ => return Err(anyhow!("Unknown parameter {}", name)),
+            }
+        }
+        Ok(subscription)
+    }
+
+    fn update(&mut self, message: &str) -> Result<(), Error> {
+        let update: SubscriptionUpdate = serde_json::from_str(message)?;
+        if let Some(universes) = update.universes {
+            self.universes = universes.iter()
+                .map(|universe| universe.port_address().map(PortAddress::value))
+                .collect::<Result<_, _>>()?;
+        }
+        if let Some(format) = update.format {
+            self.format = format;
+        }
+        if let Some(fps) = update.fps {
+            self.set_fps(fps)?;
+        }
+        Ok(())
+    }
+
+    fn set_fps(&mut self, fps: u8) -> Result<(), Error> {
+        if fps == 0 || fps > MAX_FPS {
+            return Err(anyhow!("Frame rate {} is out of range (must be 1-{})", fps, MAX_FPS));
+        }
+        self.fps = fps;
+        Ok(())
+    }
+
+    fn includes(&self, universe: u16) -> bool {
+        self.universes.is_empty() || self.universes.contains(&universe)
+    }
+}
+
+// JSON frames describe the universe, where it's sent, and its levels as received and as sent
+pub fn json_frame(universe: u16, stats: &UniverseStats, destinations: &[String]) -> Vec<u8> {
+    json!({
+        "universe": universe,
+        "artnet": PortAddress::from(universe).to_string(),
+        "source": stats.source.to_string(),
+        "received": stats.received.duration_since(UNIX_EPOCH).map(|time| time.as_millis() as u64).unwrap_or(0),
+        "destinations": destinations,
+        "input": stats.data,
+        "output": stats.output,
+    }).to_string().into_bytes()
+}
+
+// Binary frames are the universe and the input length as big-endian 16-bit numbers, then the input
+// levels, then the output levels
+pub fn binary_frame(universe: u16, stats: &UniverseStats) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(4 + stats.data.len() + stats.output.len());
+    frame.extend_from_slice(&universe.to_be_bytes());
+    frame.extend_from_slice(&(stats.data.len() as u16).to_be_bytes());
+    frame.extend_from_slice(&stats.data);
+    frame.extend_from_slice(&stats.output);
+    frame
+}
+
+// Streams universes to a WebSocket client after the handshake, until either side closes the
+// connection. Each universe is sent at most once per frame interval, and only when new data has
+// arrived for it. Messages from the client are read on a second thread.
+pub fn run(stream: TcpStream, subscription: Subscription, stats: Arc<Stats>, destinations: Arc<RwLock<Destinations>>) -> Result<(), Error> {
+    let subscription = Arc::new(Mutex::new(subscription));
+    let writer = Arc::new(Mutex::new(stream.try_clone()?));
+    let closed = Arc::new(AtomicBool::new(false));
+    stream.set_read_timeout(None)?;
+
+    let reader = {
+        let (subscription, writer, closed) = (subscription.clone(), writer.clone(), closed.clone());
+        thread::spawn(move || {
+            let mut reader = BufReader::new(stream);
+            loop {
+                match websocket::read_message(&mut reader) {
+                    Ok(Message::Text(text)) => {
+                        if let Err(e) = subscription.lock().unwrap().update(&text) {
+                            let error = json!({ "error": e.to_string() }).to_string();
+                            let _ = websocket::write_frame(&mut *writer.lock().unwrap(), websocket::TEXT, error.as_bytes());
+                        }
+                    },
+                    Ok(Message::Ping(payload)) => {
+                        let _ = websocket::write_frame(&mut *writer.lock().unwrap(), websocket::PONG, &payload);
+                    },
+                    Ok(Message::Binary(_)) | Ok(Message::Pong) => {},
+                    Ok(Message::Close) | Err(_) => break,
+                }
+            }
+            closed.store(true, Ordering::Relaxed);
+        })
+    };
+
+    let mut last_sent: HashMap<u16, u64> = HashMap::new();
+    let result = loop {
+        if closed.load(Ordering::Relaxed) {
+            break Ok(());
+        }
+        let subscription = subscription.lock().unwrap().clone();
+
+        // The receive thread updates these for every packet, so they're copied out rather than
+        // encoded while it waits
+        let updated: Vec<(u16, UniverseStats)> = stats.universes.lock().unwrap().iter()
+            .filter(|(universe, universe_stats)| subscription.includes(**universe) && last_sent.get(universe) != Some(&universe_stats.packets))
+            .map(|(&universe, universe_stats)| (universe, universe_stats.clone()))
+            .collect();
+
+        let summaries = destinations.read().unwrap().summaries();
+        let mut frames = vec!();
+        for (universe, universe_stats) in &updated {
+            last_sent.insert(*universe, universe_stats.packets);
+            frames.push(match subscription.format {
+                Format::Json => {
+                    let names: Vec<String> = summaries.iter()
+                        .filter(|summary| summary.port_address == *universe)
+                        .map(|summary| summary.name.clone())
+                        .collect();
+                    (websocket::TEXT, json_frame(*universe, universe_stats, &names))
+                },
+                Format::Binary => (websocket::BINARY, binary_frame(*universe, universe_stats)),
+            });
+        }
+
+        let sent = frames.iter()
+            .try_for_each(|(opcode, frame)| websocket::write_frame(&mut *writer.lock().unwrap(), *opcode, frame));
+        if let Err(e) = sent {
+            break Err(e.into());
+        }
+        thread::sleep(Duration::from_secs(1) / subscription.fps as u32);
+    };
+
+    let mut writer = writer.lock().unwrap();
+    let _ = websocket::write_frame(&mut *writer, websocket::CLOSE, &[]);
+    let _ = writer.shutdown(Shutdown::Both);
+    drop(writer);
+    let _ = reader.join();
+    debug!("WebSocket monitor closed");
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+    use crate::stats::FrameRate;
+
+    #[test]
+    fn test_subscription() {
+        assert_eq!(Subscription::from_query("").unwrap(), Subscription::default());
+        let mut subscription = Subscription::from_query("universes=1,17&format=binary&fps=20").unwrap();
+        assert_eq!(subscription, Subscription { universes: vec!(1, 17).into_iter().collect(), format: Format::Binary, fps: 20 });
+        assert!(subscription.includes(17) && !subscription.includes(2));
+
+        subscription.update(r#"{ "universes": [], "format": "json" }"#).unwrap();
+        assert!(subscription.includes(2));
+        assert_eq!((subscription.format, subscription.fps), (Format::Json, 20));
+
+        // Either way, universes can be numbers or net:subnet:universe
+        let expected: BTreeSet<u16> = vec!(1, 17).into_iter().collect();
+        assert_eq!(Subscription::from_query("universes=0:0:1,0:1:1").unwrap().universes, expected);
+        subscription.update(r#"{ "universes": ["0:0:1", 17] }"#).unwrap();
+        assert_eq!(subscription.universes, expected);
+        subscription.update(r#"{ "universes": ["1", "0:1:1"] }"#).unwrap();
+        assert_eq!(subscription.universes, expected);
+
+        for query in ["universes=32768", "universes=0:16:0", "format=xml", "fps=0", "fps=45", "colour=red"] {
+            Subscription::from_query(query).expect_err(&format!("Expected {} to fail", query));
+        }
+        for message in ["[]", r#"{ "fps": 100 }"#, r#"{ "universe": 1 }"#, r#"{ "universes": ["0:16:0"] }"#] {
+            subscription.update(message).expect_err(&format!("Expected {} to fail", message));
+        }
+    }
+
+    #[test]
+    fn test_frames() {
+        let stats = UniverseStats {
+            packets: 1,
+            source: "10.0.0.5".parse().unwrap(),
+            received: SystemTime::now(),
+            data: vec!(10, 20),
+            output: vec!(5, 10),
+            frame_rate: FrameRate::default(),
+        };
+        assert_eq!(binary_frame(258, &stats), vec!(1, 2, 0, 2, 10, 20, 5, 10));
+
+        let frame: serde_json::Value = serde_json::from_slice(&json_frame(17, &stats, &["KiNET 10.0.0.1 port 1".to_string()])).unwrap();
+        assert_eq!((&frame["artnet"], &frame["input"], &frame["output"]), (&json!("0:1:1"), &json!([10, 20]), &json!([5, 10])));
+        assert_eq!(frame["destinations"], json!(["KiNET 10.0.0.1 port 1"]));
+    }
+}
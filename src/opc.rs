@@ -0,0 +1,173 @@
+use std::collections::HashMap;
+use std::io::{self, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info};
+
+use crate::config::{Destinations, OpcDestination};
+use crate::pipeline::{self, Mailbox};
+use crate::stats::Stats;
+use crate::utils::{self, RateLimit};
+
+pub const OPC_PORT: u16 = 7890;
+
+// A universe holds 170 RGB pixels
+pub const MAX_PIXELS: u16 = 170;
+
+const SET_PIXEL_COLORS: u8 = 0;
+
+// Connections are made from the server's output thread, so don't let an unreachable server hold up
+// its frames for long, and don't try it again on every packet
+const CONNECT_TIMEOUT: Duration = Duration::from_millis(500);
+const WRITE_TIMEOUT: Duration = Duration::from_millis(500);
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+
+// An Open Pixel Control message: channel, command, big-endian data length, then the data
+pub fn set_pixel_colors(channel: u8, pixels: &[u8]) -> Vec<u8> {
+    let mut message = Vec::with_capacity(4 + pixels.len());
+    message.push(channel);
+    message.push(SET_PIXEL_COLORS);
+    message.extend_from_slice(&(pixels.len() as u16).to_be_bytes());
+    message.extend_from_slice(pixels);
+    message
+}
+
+// Sends Art-Net universes to OPC servers over TCP. Each server channel keeps a frame of pixels that
+// universes are written into at their pixel offset, so several universes can make up one long strip,
+// and the whole frame is sent whenever any of them arrives.
+#[derive(Default)]
+pub struct OpcOutput {
+    connections: HashMap<String, Connection>,
+    frames: HashMap<(String, u8), Vec<u8>>,
+}
+
+struct Connection {
+    stream: Option<TcpStream>,
+    reconnect: RateLimit,
+}
+
+impl OpcOutput {
+    // Returns an error when connecting or sending fails. While waiting to reconnect to a server
+    // that failed, frames for it are quietly dropped.
+    pub fn send(&mut self, destination: &OpcDestination, data: &[u8]) -> io::Result<()> {
+        let frame = self.frames.entry((destination.opc_address.clone(), destination.opc_channel)).or_default();
+        utils::write_pixels(frame, destination.pixel_offset as usize, destination.pixels.map(|pixels| pixels as usize), data);
+        let message = set_pixel_colors(destination.opc_channel, frame);
+
+        let connection = self.connections.entry(destination.opc_address.clone())
+            .or_insert_with(|| Connection { stream: None, reconnect: RateLimit::new(RECONNECT_INTERVAL) });
+
+        if connection.stream.is_none() {
+            if !connection.reconnect.ready(Instant::now()) {
+                return Ok(());
+            }
+            connection.stream = Some(connect(&destination.opc_address)?);
+            info!("Connected to OPC server {}", destination.opc_address);
+        }
+
+        if let Some(stream) = &mut connection.stream {
+            if let Err(e) = stream.write_all(&message) {
+                connection.stream = None;
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+}
+
+// Starts the output thread for one OPC server, so a server that is slow to connect or accept data
+// doesn't hold up the others. Destinations are looked up as frames are sent, and frames for a
+// universe that has since been mapped to another server are dropped, as that server's thread sends
+// them now. A thread stays idle if its server is no longer mapped.
+pub fn spawn(opc_address: String, destinations: Arc<RwLock<Destinations>>, stats: Arc<Stats>) -> Arc<Mailbox> {
+    let mut opc_output = OpcOutput::default();
+    pipeline::spawn("OPC output", stats.clone(), move |output| {
+        // OPC only carries pixel levels. Cloned so the lock isn't held while sending.
+        let destination = destinations.read().unwrap().opc.get(&output.port_address.value())
+            .filter(|destination| destination.opc_address == opc_address)
+            .cloned();
+        if let Some(destination) = destination {
+            if output.start_code == 0 {
+                debug!("Sending OPC pixels to {}", destination);
+                let result = opc_output.send(&destination, &output.data);
+                if let Err(e) = &result {
+                    error!("Could not send to OPC server {}: {}", destination.opc_address, e);
+                }
+                stats.record_send(destination.to_string(), &result);
+            }
+        }
+    })
+}
+
+fn connect(address: &str) -> io::Result<TcpStream> {
+    let mut result = Err(io::Error::new(io::ErrorKind::NotFound, format!("Could not resolve {}", address)));
+    for socket_addr in address.to_socket_addrs()? {
+        result = TcpStream::connect_timeout(&socket_addr, CONNECT_TIMEOUT);
+        if result.is_ok() {
+            break;
+        }
+    }
+
+    let stream = result?;
+    stream.set_nodelay(true)?;
+    stream.set_write_timeout(Some(WRITE_TIMEOUT))?;
+    Ok(stream)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use std::net::TcpListener;
+    use crate::artnet::PortAddress;
+    use crate::pipeline::Frame;
+
+    fn destination(opc_address: &str, pixel_offset: u16, pixels: Option<u16>) -> OpcDestination {
+        OpcDestination {
+            port_address: PortAddress::from_flat(1).unwrap(),
+            opc_address: opc_address.to_string(),
+            opc_channel: 2,
+            pixel_offset,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn test_send_to_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+
+        let mut output = OpcOutput::default();
+        output.send(&destination(&address, 1, Some(1)), &[10, 20, 30]).unwrap();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut message = [0; 10];
+        stream.read_exact(&mut message).unwrap();
+        assert_eq!(message, [2, 0, 0, 6, 0, 0, 0, 10, 20, 30]);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let listeners = [TcpListener::bind("127.0.0.1:0").unwrap(), TcpListener::bind("127.0.0.1:0").unwrap()];
+        let addresses: Vec<String> = listeners.iter().map(|listener| listener.local_addr().unwrap().to_string()).collect();
+        let mut destinations = Destinations::default();
+        destinations.opc.insert(1, destination(&addresses[0], 0, None));
+        destinations.opc.insert(2, OpcDestination { port_address: PortAddress::from_flat(2).unwrap(), ..destination(&addresses[1], 0, None) });
+        let destinations = Arc::new(RwLock::new(destinations));
+        let frame = |universe: u16, data: &[u8]| Frame {
+            port_address: PortAddress::from_flat(universe).unwrap(), sequence: 0, physical: 0, start_code: 0, data: data.to_vec(),
+        };
+
+        // Universe 1 isn't mapped to the second server, so only universe 2 is sent there
+        let mailbox = spawn(addresses[1].clone(), destinations.clone(), Arc::new(Stats::default()));
+        mailbox.put(frame(1, &[1, 2, 3]));
+        mailbox.put(frame(2, &[4, 5, 6]));
+
+        let (mut stream, _) = listeners[1].accept().unwrap();
+        let mut message = [0; 7];
+        stream.read_exact(&mut message).unwrap();
+        assert_eq!(message, [2, 0, 0, 3, 4, 5, 6]);
+    }
+}
@@ -0,0 +1,346 @@
+use std::convert::TryFrom;
+use std::fmt;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use anyhow::{anyhow, Error};
+use log::{debug, error, warn};
+
+use crate::control::Control;
+use crate::stats::Stats;
+use crate::utils::{parse_universe, RateLimit};
+
+const BUNDLE_ID: &[u8; 8] = b"#bundle\0";
+const MAX_PACKET_SIZE: usize = 4096;
+
+// Status is sent to the most recent clients to send a message
+const MAX_CLIENTS: usize = 8;
+
+// The OSC argument types the bridge understands
+#[derive(Debug, Clone, PartialEq)]
+pub enum Argument {
+    Int(i32),
+    Float(f32),
+    String(String),
+    Bool(bool),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Message {
+    pub address: String,
+    pub arguments: Vec<Argument>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    NotOsc,
+    Truncated,
+    UnsupportedType(char),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::NotOsc => write!(f, "Not an OSC packet"),
+            ParseError::Truncated => write!(f, "OSC packet is truncated"),
+            ParseError::UnsupportedType(tag) => write!(f, "OSC argument type '{}' is not supported", tag),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+impl Message {
+    pub fn new(address: &str, arguments: Vec<Argument>) -> Self {
+        Message { address: address.to_string(), arguments }
+    }
+
+    // Every part of an OSC message is padded to a multiple of 4 bytes, and numbers are big-endian
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut packet = vec!();
+        write_string(&mut packet, &self.address);
+        let tags: String = std::iter::once(',')
+            .chain(self.arguments.iter().map(|argument| match argument {
+                Argument::Int(_) => 'i',
+                Argument::Float(_) => 'f',
+                Argument::String(_) => 's',
+                Argument::Bool(true) => 'T',
+                Argument::Bool(false) => 'F',
+            }))
+            .collect();
+        write_string(&mut packet, &tags);
+        for argument in &self.arguments {
+            match argument {
+                Argument::Int(value) => packet.extend_from_slice(&value.to_be_bytes()),
+                Argument::Float(value) => packet.extend_from_slice(&value.to_be_bytes()),
+                Argument::String(value) => write_string(&mut packet, value),
+                Argument::Bool(_) => {},
+            }
+        }
+        packet
+    }
+}
+
+fn write_string(packet: &mut Vec<u8>, value: &str) {
+    packet.extend_from_slice(value.as_bytes());
+    let padding = 4 - value.len() % 4;
+    packet.resize(packet.len() + padding, 0);
+}
+
+// Parses a message, or every message in a bundle. Time tags are ignored, so bundles take effect
+// as soon as they arrive.
+pub fn parse(buffer: &[u8]) -> Result<Vec<Message>, ParseError> {
+    let mut messages = vec!();
+    parse_into(buffer, &mut messages)?;
+    Ok(messages)
+}
+
+fn parse_into(buffer: &[u8], messages: &mut Vec<Message>) -> Result<(), ParseError> {
+    if buffer.starts_with(BUNDLE_ID) {
+        let mut offset = 16;
+        while offset < buffer.len() {
+            // A negative size can't be honoured any more than one running past the end
+            let size = usize::try_from(read_i32(buffer, &mut offset)?).map_err(|_| ParseError::Truncated)?;
+            let end = offset.checked_add(size).ok_or(ParseError::Truncated)?;
+            let element = buffer.get(offset..end).ok_or(ParseError::Truncated)?;
+            parse_into(element, messages)?;
+            offset = end;
+        }
+        return Ok(());
+    }
+
+    if !buffer.starts_with(b"/") {
+        return Err(ParseError::NotOsc);
+    }
+    let mut offset = 0;
+    let address = read_string(buffer, &mut offset)?;
+    // Very old clients leave the type tags out altogether
+    let tags = if offset < buffer.len() { read_string(buffer, &mut offset)? } else { ",".to_string() };
+
+    let mut arguments = vec!();
+    for tag in tags.chars().skip(1) {
+        arguments.push(match tag {
+            'i' => Argument::Int(read_i32(buffer, &mut offset)?),
+            'f' => Argument::Float(f32::from_bits(read_i32(buffer, &mut offset)? as u32)),
+            's' => Argument::String(read_string(buffer, &mut offset)?),
+            'T' => Argument::Bool(true),
+            'F' => Argument::Bool(false),
+            tag => return Err(ParseError::UnsupportedType(tag)),
+        });
+    }
+    messages.push(Message { address, arguments });
+    Ok(())
+}
+
+fn read_i32(buffer: &[u8], offset: &mut usize) -> Result<i32, ParseError> {
+    let bytes = buffer.get(*offset..*offset + 4).ok_or(ParseError::Truncated)?;
+    *offset += 4;
+    Ok(i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+fn read_string(buffer: &[u8], offset: &mut usize) -> Result<String, ParseError> {
+    let rest = buffer.get(*offset..).ok_or(ParseError::Truncated)?;
+    let length = rest.iter().position(|&byte| byte == 0).ok_or(ParseError::Truncated)?;
+    let value = String::from_utf8_lossy(&rest[..length]).into_owned();
+    *offset += (length / 4 + 1) * 4;
+    Ok(value)
+}
+
+// Listens for OSC messages that control the bridge, and sends the resulting status back to the
+// clients that sent them
+pub fn spawn(socket: UdpSocket, control: Arc<Control>, stats: Arc<Stats>) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        let mut clients: Vec<SocketAddr> = vec!();
+        let mut parse_warning = RateLimit::new(Duration::from_secs(10));
+        let mut buffer = [0u8; MAX_PACKET_SIZE];
+
+        loop {
+            let (length, addr) = match socket.recv_from(&mut buffer) {
+                Ok(received) => received,
+                Err(e) => {
+                    error!("Stopped receiving OSC: {}", e);
+                    return;
+                }
+            };
+
+            let messages = match parse(&buffer[..length]) {
+                Ok(messages) => messages,
+                Err(e) => {
+                    if parse_warning.ready(Instant::now()) {
+                        warn!("Ignoring packet from {} on the OSC port: {}", addr, e);
+                    }
+                    continue;
+                }
+            };
+
+            for message in &messages {
+                match handle(&control, message) {
+                    Ok(()) => debug!("Handled OSC message {:?} from {}", message, addr),
+                    Err(e) => warn!("Could not handle OSC message {} from {}: {}", message.address, addr, e),
+                }
+            }
+
+            clients.retain(|client| *client != addr);
+            clients.push(addr);
+            if clients.len() > MAX_CLIENTS {
+                clients.remove(0);
+            }
+            for message in status(&control, &stats) {
+                let bytes = message.to_bytes();
+                for client in &clients {
+                    if let Err(e) = socket.send_to(&bytes, client) {
+                        debug!("Could not send OSC status to {}: {}", client, e);
+                    }
+                }
+            }
+        }
+    })
+}
+
+fn handle(control: &Control, message: &Message) -> Result<(), Error> {
+    let parts: Vec<&str> = message.address.trim_start_matches('/').split('/').collect();
+    let argument = message.arguments.first();
+
+    match parts.as_slice() {
+        // Faders send 0-1, and integers are taken as DMX levels
+        ["master"] => match argument {
+            Some(Argument::Float(level)) => control.set_master(*level),
+            Some(Argument::Int(level)) if (0..=255).contains(level) => control.set_master(*level as f32 / 255.0),
+            _ => Err(anyhow!("Expected a level from 0-1 or 0-255")),
+        },
+        ["blackout"] => {
+            match argument {
+                None => control.toggle_blackout(),
+                Some(argument) => control.set_blackout(is_on(argument)?),
+            }
+            Ok(())
+        },
+        ["scene", "recall"] => match argument {
+            Some(Argument::String(name)) => control.recall_scene(name),
+            Some(Argument::Int(number)) => control.recall_scene(&number.to_string()),
+            _ => Err(anyhow!("Expected a scene name or number")),
+        },
+        ["universe", universe, "channel", channel] => {
+            let channel = channel.parse::<u16>().map_err(|_| anyhow!("Could not understand channel {}", channel))?;
+            control.set_channel(parse_universe(universe)?, channel, channel_level(argument)?)
+        },
+        ["mapping", universe, "enable"] => {
+            let enabled = match argument {
+                Some(argument) => is_on(argument)?,
+                None => return Err(anyhow!("Expected 0 or 1")),
+            };
+            control.set_enabled(parse_universe(universe)?, enabled);
+            Ok(())
+        },
+        ["status"] => Ok(()),
+        _ => Err(anyhow!("Unknown address")),
+    }
+}
+
+fn is_on(argument: &Argument) -> Result<bool, Error> {
+    match argument {
+        Argument::Bool(on) => Ok(*on),
+        Argument::Int(value) => Ok(*value != 0),
+        Argument::Float(value) => Ok(*value >= 0.5),
+        Argument::String(_) => Err(anyhow!("Expected 0 or 1")),
+    }
+}
+
+// A level of 0-255, or 0-1 from a fader. A negative level releases the channel.
+fn channel_level(argument: Option<&Argument>) -> Result<Option<u8>, Error> {
+    match argument {
+        Some(Argument::Int(level)) if *level < 0 => Ok(None),
+        Some(Argument::Int(level)) if *level <= 255 => Ok(Some(*level as u8)),
+        Some(Argument::Float(level)) if *level < 0.0 => Ok(None),
+        Some(Argument::Float(level)) if *level <= 1.0 => Ok(Some((level * 255.0).round() as u8)),
+        _ => Err(anyhow!("Expected a level from 0-1 or 0-255, or -1 to release the channel")),
+    }
+}
+
+fn status(control: &Control, stats: &Stats) -> Vec<Message> {
+    let state = control.state();
+    vec!(
+        Message::new("/status/master", vec!(Argument::Float(state.master))),
+        Message::new("/status/blackout", vec!(Argument::Int(state.blackout as i32))),
+        Message::new("/status/scene", vec!(Argument::String(state.scene.clone().unwrap_or_default()))),
+        Message::new("/status/disabled", state.disabled.iter().map(|&universe| Argument::Int(universe as i32)).collect()),
+        Message::new("/status/artnet/received", vec!(Argument::Int(Stats::get(&stats.artnet_received) as i32))),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    #[test]
+    fn test_encode_and_parse() {
+        let message = Message::new("/universe/1/channel/2", vec!(Argument::Float(0.5), Argument::Int(-1), Argument::String("abc".to_string()), Argument::Bool(true)));
+        let bytes = message.to_bytes();
+        assert_eq!(bytes[..32], *b"/universe/1/channel/2\0\0\0,fisT\0\0\0");
+        assert_eq!(bytes.len(), 44);
+        assert_eq!(parse(&bytes).unwrap(), vec!(message));
+
+        // Bundles hold several messages, each with its size
+        let first = Message::new("/master", vec!(Argument::Float(1.0))).to_bytes();
+        let second = Message::new("/blackout", vec!()).to_bytes();
+        let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+        for element in &[&first, &second] {
+            bundle.extend_from_slice(&(element.len() as i32).to_be_bytes());
+            bundle.extend_from_slice(element);
+        }
+        assert_eq!(parse(&bundle).unwrap().len(), 2);
+
+        // Element sizes that are negative or run past the end of the bundle
+        for size in &[-1i32, -4, i32::MIN, 1000] {
+            let mut bundle = b"#bundle\0\0\0\0\0\0\0\0\x01".to_vec();
+            bundle.extend_from_slice(&size.to_be_bytes());
+            bundle.extend_from_slice(&first);
+            assert_eq!(parse(&bundle), Err(ParseError::Truncated), "Expected element size {} to fail", size);
+        }
+
+        assert_eq!(parse(b"Art-Net\0"), Err(ParseError::NotOsc));
+        assert_eq!(parse(b"/master\0,f\0\0"), Err(ParseError::Truncated));
+        assert_eq!(parse(b"/master\0,d\0\0\0\0\0\0\0\0\0\0"), Err(ParseError::UnsupportedType('d')));
+        assert_eq!(parse(b"/status\0").unwrap(), vec!(Message::new("/status", vec!())));
+    }
+
+    #[test]
+    fn test_handle() {
+        let control = Control::new(HashMap::new());
+        let message = |address: &str, arguments: Vec<Argument>| Message::new(address, arguments);
+
+        handle(&control, &message("/master", vec!(Argument::Float(0.25)))).unwrap();
+        assert_eq!(control.state().master, 0.25);
+        handle(&control, &message("/master", vec!(Argument::Int(255)))).unwrap();
+        assert_eq!(control.state().master, 1.0);
+
+        handle(&control, &message("/blackout", vec!())).unwrap();
+        assert!(control.state().blackout);
+        handle(&control, &message("/blackout", vec!(Argument::Int(0)))).unwrap();
+        assert!(!control.state().blackout);
+
+        handle(&control, &message("/universe/3/channel/10", vec!(Argument::Float(1.0)))).unwrap();
+        assert_eq!(control.state().overrides[&3][&10], 255);
+        handle(&control, &message("/universe/3/channel/10", vec!(Argument::Int(-1)))).unwrap();
+        assert!(control.state().overrides.is_empty());
+
+        handle(&control, &message("/mapping/4/enable", vec!(Argument::Bool(false)))).unwrap();
+        assert!(control.state().disabled.contains(&4));
+
+        let bad_messages = vec!(
+            message("/master", vec!(Argument::Float(2.0))),
+            message("/universe/32768/channel/1", vec!(Argument::Int(0))),
+            message("/universe/1/channel/0", vec!(Argument::Int(0))),
+            message("/universe/1/channel/1", vec!(Argument::Int(256))),
+            message("/scene/recall", vec!(Argument::String("missing".to_string()))),
+            message("/mapping/1/enable", vec!()),
+            message("/unknown", vec!()),
+        );
+        for bad in bad_messages {
+            handle(&control, &bad).expect_err(format!("Expected message to fail, but it didn't: {:?}", bad).as_str());
+        }
+    }
+}
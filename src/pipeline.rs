@@ -0,0 +1,142 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::artnet::{Dmx, PortAddress};
+use crate::stats::Stats;
+
+// Packets are received, processed and sent by separate threads, so a slow send or a burst of
+// logging in one stage doesn't hold up receiving the next packet. Stages are joined by mailboxes
+// rather than queues: each one holds at most one frame per universe and start code, and a newer
+// frame replaces one that hasn't been picked up yet. A stage that falls behind skips stale frames
+// instead of building up a backlog, and a mailbox can never grow beyond the universes in use.
+
+// A universe of ArtDmx data, owned so it can be handed between stages
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub port_address: PortAddress,
+    pub sequence: u8,
+    pub physical: u8,
+    pub start_code: u8,
+    pub data: Vec<u8>,
+}
+
+impl From<&Dmx<'_>> for Frame {
+    fn from(dmx: &Dmx<'_>) -> Self {
+        Frame {
+            port_address: dmx.port_address,
+            sequence: dmx.sequence,
+            physical: dmx.physical,
+            start_code: dmx.start_code,
+            data: dmx.data.to_vec(),
+        }
+    }
+}
+
+impl Frame {
+    fn key(&self) -> (u16, u8) {
+        (self.port_address.value(), self.start_code)
+    }
+}
+
+pub struct Mailbox {
+    stage: &'static str,
+    stats: Arc<Stats>,
+    pending: Mutex<Pending>,
+    ready: Condvar,
+}
+
+// Frames waiting to be taken, in the order their universes first arrived
+#[derive(Default)]
+struct Pending {
+    order: VecDeque<(u16, u8)>,
+    frames: HashMap<(u16, u8), Frame>,
+}
+
+impl Mailbox {
+    pub fn new(stage: &'static str, stats: Arc<Stats>) -> Self {
+        Mailbox { stage, stats, pending: Mutex::new(Pending::default()), ready: Condvar::new() }
+    }
+
+    // Never blocks. A frame that replaces one still waiting keeps its place, and is counted as
+    // superseded for the stage.
+    pub fn put(&self, frame: Frame) {
+        let key = frame.key();
+        let mut pending = self.pending.lock().unwrap();
+        match pending.frames.insert(key, frame) {
+            Some(_) => self.stats.record_superseded(self.stage),
+            None => {
+                pending.order.push_back(key);
+                self.ready.notify_one();
+            }
+        }
+    }
+
+    // Waits for the next frame
+    pub fn take(&self) -> Frame {
+        let mut pending = self.pending.lock().unwrap();
+        loop {
+            if let Some(key) = pending.order.pop_front() {
+                return pending.frames.remove(&key).expect("Queued frame is missing");
+            }
+            pending = self.ready.wait(pending).unwrap();
+        }
+    }
+}
+
+// Starts a stage on its own thread, handling each frame put into the mailbox it returns
+pub fn spawn<F>(stage: &'static str, stats: Arc<Stats>, mut handle: F) -> Arc<Mailbox>
+where
+    F: FnMut(Frame) + Send + 'static,
+{
+    let mailbox = Arc::new(Mailbox::new(stage, stats));
+    let frames = mailbox.clone();
+    thread::Builder::new()
+        .name(stage.to_string())
+        .spawn(move || loop {
+            handle(frames.take());
+        })
+        .expect("Could not start thread");
+    mailbox
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    fn frame(universe: u16, start_code: u8, data: &[u8]) -> Frame {
+        Frame { port_address: PortAddress::from(universe), sequence: 0, physical: 0, start_code, data: data.to_vec() }
+    }
+
+    #[test]
+    fn test_mailbox() {
+        let stats = Arc::new(Stats::default());
+        let mailbox = Mailbox::new("test", stats.clone());
+        mailbox.put(frame(1, 0, &[1]));
+        mailbox.put(frame(2, 0, &[2]));
+        mailbox.put(frame(1, 0, &[3]));
+        mailbox.put(frame(1, 0x17, &[4]));
+
+        // The newest frame for universe 1 is sent in the place of the first
+        assert_eq!(mailbox.take(), frame(1, 0, &[3]));
+        assert_eq!(mailbox.take(), frame(2, 0, &[2]));
+        assert_eq!(mailbox.take(), frame(1, 0x17, &[4]));
+        assert_eq!(stats.superseded.lock().unwrap()["test"], 1);
+    }
+
+    #[test]
+    fn test_spawn() {
+        let (sender, receiver) = mpsc::channel();
+        let mailbox = spawn("test", Arc::new(Stats::default()), move |frame| sender.send(frame).unwrap());
+        for universe in 0..100 {
+            mailbox.put(frame(universe, 0, &[universe as u8]));
+        }
+
+        let received: Vec<u16> = (0..100)
+            .map(|_| receiver.recv_timeout(Duration::from_secs(5)).unwrap().port_address.value())
+            .collect();
+        assert_eq!(received, (0..100).collect::<Vec<u16>>());
+    }
+}
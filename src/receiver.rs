@@ -0,0 +1,131 @@
+use std::io::{self, ErrorKind};
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use log::{debug, warn};
+
+use crate::artnet::{self, Packet, MAX_PACKET_SIZE};
+use crate::stats::Stats;
+use crate::utils::RateLimit;
+
+const WARNING_INTERVAL: Duration = Duration::from_secs(10);
+
+// Reads packets from the Art-Net socket. Anything on the port that can't be parsed is counted and
+// skipped, as are errors that only affect one packet, so that a stray packet can't stop the bridge.
+// Errors that leave the socket unusable are returned, as are timeouts, so a caller that sets one
+// gets control back.
+pub struct Receiver {
+    socket: UdpSocket,
+    buffer: [u8; MAX_PACKET_SIZE],
+    stats: Arc<Stats>,
+    parse_warning: RateLimit,
+    socket_warning: RateLimit,
+}
+
+impl Receiver {
+    pub fn new(socket: UdpSocket, stats: Arc<Stats>) -> Self {
+        Receiver {
+            socket,
+            buffer: [0u8; MAX_PACKET_SIZE],
+            stats,
+            parse_warning: RateLimit::new(WARNING_INTERVAL),
+            socket_warning: RateLimit::new(WARNING_INTERVAL),
+        }
+    }
+
+    // Receives one datagram, returning None if it was skipped. The caller loops rather than this,
+    // since a packet borrowing the buffer can't be handed out of a loop that reuses it.
+    pub fn receive(&mut self) -> io::Result<Option<(Packet<'_>, SocketAddr)>> {
+        let (length, addr) = match self.socket.recv_from(&mut self.buffer) {
+            Ok(received) => received,
+            Err(e) if is_transient(&e) => {
+                debug!("Error receiving Art-Net: {}", e);
+                if self.socket_warning.ready(Instant::now()) {
+                    warn!("Error receiving Art-Net, carrying on: {}", e);
+                }
+                return Ok(None);
+            },
+            Err(e) => return Err(e),
+        };
+
+        match artnet::parse(&self.buffer[..length]) {
+            Ok(packet) => Ok(Some((packet, addr))),
+            Err(e) => {
+                Stats::increment(&self.stats.artnet_parse_errors);
+                debug!("Ignoring packet from {} on the Art-Net port: {}", addr, e);
+                if self.parse_warning.ready(Instant::now()) {
+                    warn!("Ignored {} packets on the Art-Net port so far, most recently from {}: {}",
+                        Stats::get(&self.stats.artnet_parse_errors), addr, e);
+                }
+                Ok(None)
+            }
+        }
+    }
+}
+
+// Errors from a single packet rather than the socket: a signal interrupting the call, or an ICMP
+// unreachable from an earlier send, which Windows reports as a reset on the next receive
+fn is_transient(e: &io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::Interrupted | ErrorKind::ConnectionReset | ErrorKind::ConnectionRefused)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_receive_skips_garbage() {
+        let socket = UdpSocket::bind("127.0.0.1:0").unwrap();
+        socket.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+        let address = socket.local_addr().unwrap();
+        let stats = Arc::new(Stats::default());
+        let mut receiver = Receiver::new(socket, stats.clone());
+
+        let mut dmx = b"Art-Net\0\x00\x50\x00\x0e\x01\x00\x11\x00\x00\x02".to_vec();
+        dmx.extend_from_slice(&[10, 20]);
+        let garbage = [
+            vec!(),
+            b"hello".to_vec(),
+            b"Art-Net\0".to_vec(),
+            // ArtDmx claiming 512 channels but carrying 2
+            [&dmx[..16], &[0x02, 0x00, 10, 20]].concat(),
+            // ArtDmx longer than a universe
+            [&dmx[..16], &[0x02, 0x02]].concat(),
+            dmx[..17].to_vec(),
+            (0..=255).collect(),
+        ];
+        let sender = UdpSocket::bind("127.0.0.1:0").unwrap();
+        for packet in &garbage {
+            sender.send_to(packet, address).unwrap();
+        }
+        sender.send_to(&dmx, address).unwrap();
+
+        for _ in &garbage {
+            assert!(receiver.receive().unwrap().is_none());
+        }
+        match receiver.receive().unwrap() {
+            Some((Packet::Dmx(dmx), addr)) => {
+                assert_eq!((dmx.port_address.value(), dmx.data), (17, &[10, 20][..]));
+                assert_eq!(addr, sender.local_addr().unwrap());
+            },
+            other => panic!("Expected ArtDmx, got {:?}", other),
+        }
+        assert_eq!(Stats::get(&stats.artnet_parse_errors), garbage.len() as u64);
+
+        // A timeout is handed back rather than waited out
+        receiver.socket.set_read_timeout(Some(Duration::from_millis(10))).unwrap();
+        let e = receiver.receive().unwrap_err();
+        assert!(matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn test_is_transient() {
+        assert!(is_transient(&io::Error::from(ErrorKind::ConnectionReset)));
+        assert!(is_transient(&io::Error::from(ErrorKind::Interrupted)));
+        assert!(!is_transient(&io::Error::from(ErrorKind::WouldBlock)));
+        assert!(!is_transient(&io::Error::from(ErrorKind::TimedOut)));
+        assert!(!is_transient(&io::Error::from(ErrorKind::PermissionDenied)));
+        assert!(!is_transient(&io::Error::from(ErrorKind::InvalidInput)));
+    }
+}
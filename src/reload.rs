@@ -0,0 +1,208 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::Ipv4Addr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Error;
+use log::{error, info, warn};
+
+use crate::config::{Configuration, Destinations, KinetSource, UserConfiguration};
+use crate::control::Scene;
+
+const POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+// Reloads the configuration when the config file changes or the process receives SIGHUP,
+// swapping in the new destination table while the sockets stay open. A configuration that
+// fails to load is rejected and the current one keeps running.
+pub struct Reloader {
+    cli_args: UserConfiguration,
+    // The file to watch, found the same way Configuration::load finds the file it reads
+    config_file: Option<String>,
+    artnet_receive_addr: Ipv4Addr,
+    kinet_send_addr: Ipv4Addr,
+    kinet_flags: u16,
+    kinet_timer: i32,
+    sacn_source_name: String,
+    sacn_cid: [u8; 16],
+    sacn_priority: u8,
+    kinet_inputs: Vec<KinetSource>,
+    osc_port: Option<u16>,
+    http_port: Option<u16>,
+    http_addr: Ipv4Addr,
+    scenes: HashMap<String, Scene>,
+}
+
+impl Reloader {
+    pub fn new(cli_args: UserConfiguration, cfg: &Configuration) -> Self {
+        // The environment was already read without errors to load cfg
+        Reloader::with_env(cli_args, UserConfiguration::from_env().unwrap_or_default(), cfg)
+    }
+
+    fn with_env(cli_args: UserConfiguration, env_args: UserConfiguration, cfg: &Configuration) -> Self {
+        Reloader {
+            config_file: cli_args.config_file.clone().or(env_args.config_file),
+            cli_args,
+            artnet_receive_addr: cfg.artnet_receive_addr,
+            kinet_send_addr: cfg.kinet_send_addr,
+            kinet_flags: cfg.kinet_flags,
+            kinet_timer: cfg.kinet_timer,
+            sacn_source_name: cfg.sacn_source_name.clone(),
+            sacn_cid: cfg.sacn_cid,
+            sacn_priority: cfg.sacn_priority,
+            kinet_inputs: cfg.kinet_inputs.clone(),
+            osc_port: cfg.osc_port,
+            http_port: cfg.http_port,
+            http_addr: cfg.http_addr,
+            scenes: cfg.scenes.clone(),
+        }
+    }
+
+    pub fn spawn(self, destinations: Arc<RwLock<Destinations>>) -> io::Result<thread::JoinHandle<()>> {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone())?;
+
+        Ok(thread::spawn(move || {
+            let mut last_modified = self.config_file_modified();
+            loop {
+                thread::sleep(POLL_INTERVAL);
+
+                let modified = self.config_file_modified();
+                let file_changed = modified != last_modified;
+                last_modified = modified;
+
+                if hangup.swap(false, Ordering::Relaxed) {
+                    info!("Received SIGHUP, reloading configuration");
+                } else if file_changed {
+                    info!("Configuration file changed, reloading configuration");
+                } else {
+                    continue;
+                }
+
+                if let Err(e) = self.reload(&destinations) {
+                    error!("Keeping current configuration, new configuration could not be loaded: {}", e);
+                }
+            }
+        }))
+    }
+
+    fn config_file_modified(&self) -> Option<SystemTime> {
+        let path = self.config_file.as_ref()?;
+        fs::metadata(path).and_then(|metadata| metadata.modified()).ok()
+    }
+
+    fn reload(&self, destinations: &RwLock<Destinations>) -> Result<(), Error> {
+        let cfg = Configuration::load(&self.cli_args)?;
+
+        if cfg.artnet_receive_addr != self.artnet_receive_addr || cfg.kinet_send_addr != self.kinet_send_addr {
+            warn!("Changes to the Art-Net and KiNET addresses only take effect after a restart");
+        }
+        if cfg.kinet_flags != self.kinet_flags || cfg.kinet_timer != self.kinet_timer {
+            warn!("Changes to the KiNET header flags and timer only take effect after a restart");
+        }
+        if cfg.sacn_source_name != self.sacn_source_name || cfg.sacn_cid != self.sacn_cid || cfg.sacn_priority != self.sacn_priority {
+            warn!("Changes to the sACN source name, CID and priority only take effect after a restart");
+        }
+        if cfg.kinet_inputs != self.kinet_inputs {
+            warn!("Changes to KiNET inputs only take effect after a restart");
+        }
+        if cfg.osc_port != self.osc_port || cfg.scenes != self.scenes {
+            warn!("Changes to the OSC port and scenes only take effect after a restart");
+        }
+        if cfg.http_port != self.http_port || cfg.http_addr != self.http_addr {
+            warn!("Changes to the HTTP port and address only take effect after a restart");
+        }
+
+        for issue in cfg.validate() {
+            warn!("{}", issue);
+        }
+
+        info!("Mapping Art-Net to the following KiNET destinations:");
+        for mapping in cfg.destinations.kinet.values() {
+            info!("{:?}", mapping);
+        }
+        if !cfg.destinations.opc.is_empty() {
+            info!("Mapping Art-Net to the following OPC destinations:");
+            for mapping in cfg.destinations.opc.values() {
+                info!("{:?}", mapping);
+            }
+        }
+        if !cfg.destinations.sacn.is_empty() {
+            info!("Mapping Art-Net to the following sACN destinations:");
+            for mapping in cfg.destinations.sacn.values() {
+                info!("{:?}", mapping);
+            }
+        }
+        if !cfg.destinations.artnet.is_empty() {
+            info!("Relaying Art-Net to the following Art-Net destinations:");
+            for mapping in cfg.destinations.artnet.values() {
+                info!("{:?}", mapping);
+            }
+        }
+        if !cfg.destinations.ddp.is_empty() {
+            info!("Mapping Art-Net to the following DDP destinations:");
+            for mapping in cfg.destinations.ddp.values() {
+                info!("{:?}", mapping);
+            }
+        }
+
+        *destinations.write().unwrap() = cfg.destinations;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_reload_keeps_old_config_on_error() {
+        let path = std::env::temp_dir().join(format!("artnet-bridge-reload-{}.json", std::process::id()));
+        fs::write(&path, r#"{ "artnet_receive_ip": "127.0.0.1", "kinet_send_ip": "127.0.0.1", "mappings": ["1:10.0.0.1"] }"#).unwrap();
+
+        let cli_args = UserConfiguration {
+            config_file: Some(path.to_string_lossy().into_owned()),
+            ..UserConfiguration::default()
+        };
+        let cfg = Configuration::load(&cli_args).unwrap();
+        let reloader = Reloader::new(cli_args, &cfg);
+        let destinations = RwLock::new(cfg.destinations);
+
+        fs::write(&path, r#"{ "artnet_receive_ip": "127.0.0.1", "kinet_send_ip": "127.0.0.1", "mappings": ["2:10.0.0.2", "3:10.0.0.3"] }"#).unwrap();
+        reloader.reload(&destinations).unwrap();
+        assert_eq!(destinations.read().unwrap().kinet.len(), 2);
+        assert_eq!(destinations.read().unwrap().kinet[&0x003].kinet_address, "10.0.0.3");
+
+        fs::write(&path, r#"{ "artnet_receive_ip": "127.0.0.1", "kinet_send_ip": "127.0.0.1", "mappings": ["2:10.0.0.2:99"] }"#).unwrap();
+        reloader.reload(&destinations).expect_err("Expected invalid configuration to be rejected");
+        assert_eq!(destinations.read().unwrap().kinet.len(), 2);
+        assert_eq!(destinations.read().unwrap().kinet[&0x002].kinet_address, "10.0.0.2");
+
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_watches_config_file_from_env() {
+        let path = std::env::temp_dir().join(format!("artnet-bridge-reload-env-{}.json", std::process::id()));
+        let path_string = path.to_string_lossy().into_owned();
+        fs::write(&path, r#"{ "artnet_receive_ip": "127.0.0.1", "kinet_send_ip": "127.0.0.1", "mappings": ["1:10.0.0.1"] }"#).unwrap();
+
+        let env_args = UserConfiguration::from_env_vars(vec!(("ARTNET_BRIDGE_CONFIG_FILE".to_string(), path_string.clone()))).unwrap();
+        let file_args = UserConfiguration::from_file(&path_string).unwrap();
+        let cfg = Configuration::from_user_configs(UserConfiguration::default(), env_args.clone(), file_args).unwrap();
+        let reloader = Reloader::with_env(UserConfiguration::default(), env_args, &cfg);
+        assert_eq!(reloader.config_file, Some(path_string));
+
+        let modified = reloader.config_file_modified();
+        assert!(modified.is_some());
+        let earlier = SystemTime::now() - Duration::from_secs(60);
+        fs::File::open(&path).unwrap().set_modified(earlier).unwrap();
+        assert_ne!(reloader.config_file_modified(), modified);
+
+        fs::remove_file(&path).unwrap();
+    }
+}
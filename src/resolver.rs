@@ -0,0 +1,128 @@
+use std::collections::{HashMap, HashSet};
+use std::net::SocketAddr;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::Duration;
+
+use log::{info, warn};
+
+use crate::config::{self, Destinations};
+
+const RESOLVE_INTERVAL: Duration = Duration::from_secs(30);
+
+// Re-resolve destinations given as hostnames in the background, so supplies and nodes that
+// pick up a new address from DHCP keep receiving output without a restart
+pub fn spawn(destinations: Arc<RwLock<Destinations>>) -> thread::JoinHandle<()> {
+    thread::spawn(move || loop {
+        thread::sleep(RESOLVE_INTERVAL);
+        refresh(&destinations);
+    })
+}
+
+fn refresh(destinations: &RwLock<Destinations>) {
+    // Don't hold the lock while waiting on the resolver
+    let hostnames: HashSet<String> = {
+        let destinations = destinations.read().unwrap();
+        destinations.kinet.values().map(|destination| &destination.kinet_address)
+            .chain(destinations.sacn.values().filter_map(|destination| destination.sacn_unicast.as_ref()))
+            .chain(destinations.artnet.values().map(|destination| &destination.artnet_node))
+            .chain(destinations.ddp.values().map(|destination| &destination.ddp_device))
+            .filter(|address| config::is_hostname(address))
+            .cloned()
+            .collect()
+    };
+
+    let mut resolved = HashMap::new();
+    for hostname in hostnames {
+        match config::resolve_ipv4_address(&hostname, 0) {
+            Ok(address) => { resolved.insert(hostname, address.ip()); },
+            Err(e) => { warn!("{}, keeping previous address", e); }
+        }
+    }
+
+    let mut changed = HashSet::new();
+    for (hostname, socket_addr) in addresses(&mut destinations.write().unwrap()) {
+        if let Some(address) = resolved.get(hostname) {
+            if *address != socket_addr.ip() {
+                if changed.insert(hostname.to_string()) {
+                    match config::is_resolved(*socket_addr) {
+                        true => info!("Destination {} changed address from {} to {}", hostname, socket_addr.ip(), address),
+                        false => info!("Destination {} resolved to {}", hostname, address),
+                    }
+                }
+                socket_addr.set_ip(*address);
+            }
+        }
+    }
+}
+
+// Each destination address as written in the configuration, with the socket address it resolved to.
+// sACN destinations sent to a multicast group have no address written down.
+fn addresses(destinations: &mut Destinations) -> impl Iterator<Item = (&str, &mut SocketAddr)> {
+    let Destinations { kinet, sacn, artnet, ddp, .. } = destinations;
+    kinet.values_mut()
+        .map(|destination| (destination.kinet_address.as_str(), &mut destination.kinet_socket_addr))
+        .chain(sacn.values_mut().filter_map(|destination| match &destination.sacn_unicast {
+            Some(sacn_unicast) => Some((sacn_unicast.as_str(), &mut destination.sacn_socket_addr)),
+            None => None,
+        }))
+        .chain(artnet.values_mut().map(|destination| (destination.artnet_node.as_str(), &mut destination.artnet_socket_addr)))
+        .chain(ddp.values_mut().map(|destination| (destination.ddp_device.as_str(), &mut destination.ddp_socket_addr)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+    use crate::artnet::PortAddress;
+    use crate::config::{ArtnetDestination, KinetDestination, SacnDestination};
+
+    #[test]
+    fn test_refresh_updates_hostnames() {
+        let stale_address = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 9)), 6038);
+        let mut table = Destinations::default();
+        table.kinet.insert(0x001, KinetDestination {
+            artnet_network: 0, artnet_subnet: 0, artnet_universe: 1, kinet_port: 0,
+            kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+            kinet_address: "localhost".to_string(),
+            kinet_socket_addr: stale_address,
+        });
+        table.kinet.insert(0x002, KinetDestination {
+            artnet_network: 0, artnet_subnet: 0, artnet_universe: 2, kinet_port: 0,
+            kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+            kinet_address: "10.0.0.9".to_string(),
+            kinet_socket_addr: stale_address,
+        });
+        // Couldn't be resolved when the configuration was loaded
+        table.kinet.insert(0x003, KinetDestination {
+            artnet_network: 0, artnet_subnet: 0, artnet_universe: 3, kinet_port: 0,
+            kinet_channels: None, kinet_trim_dmx_out: false, kinet_start_code: None,
+            kinet_address: "localhost".to_string(),
+            kinet_socket_addr: config::unresolved_address(6038),
+        });
+        // Other kinds of destination are looked up too, keeping their own ports
+        table.artnet.insert(0x001, ArtnetDestination {
+            port_address: PortAddress::from(0x001),
+            artnet_node: "localhost".to_string(),
+            artnet_socket_addr: config::unresolved_address(6454),
+            artnet_output_universe: PortAddress::from(0x001),
+        });
+        table.sacn.insert(0x001, SacnDestination {
+            port_address: PortAddress::from(0x001),
+            sacn_universe: 1,
+            sacn_unicast: Some("localhost".to_string()),
+            sacn_socket_addr: config::unresolved_address(5568),
+            sacn_priority: None,
+        });
+        let destinations = RwLock::new(table);
+
+        refresh(&destinations);
+
+        let table = destinations.read().unwrap();
+        assert_eq!(table.kinet[&0x001].kinet_socket_addr, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 6038));
+        assert_eq!(table.kinet[&0x002].kinet_socket_addr, stale_address);
+        assert_eq!(table.kinet[&0x003].kinet_socket_addr, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 6038));
+        assert_eq!(table.artnet[&0x001].artnet_socket_addr, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 6454));
+        assert_eq!(table.sacn[&0x001].sacn_socket_addr, SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 5568));
+    }
+}
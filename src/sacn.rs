@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::io;
+use std::net::{Ipv4Addr, UdpSocket};
+
+use crate::config::SacnDestination;
+
+pub const SACN_PORT: u16 = 5568;
+pub const DEFAULT_PRIORITY: u8 = 100;
+pub const MAX_PRIORITY: u8 = 200;
+pub const MAX_UNIVERSE: u16 = 63999;
+
+// Source names are sent in a 64 byte field that has to end with a null
+pub const MAX_SOURCE_NAME_LENGTH: usize = 63;
+
+const ACN_PACKET_IDENTIFIER: &[u8; 12] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+const DMP_ADDRESS_AND_DATA_TYPE: u8 = 0xa1;
+
+// Offsets of each layer's flags and length field, which counts from the start of that field
+const ROOT_LAYER: usize = 16;
+const FRAMING_LAYER: usize = 38;
+const DMP_LAYER: usize = 115;
+const HEADER_LENGTH: usize = 126;
+
+// Each sACN universe has its own multicast group, 239.255.x.y, where x.y is the universe number
+pub fn multicast_address(universe: u16) -> Ipv4Addr {
+    let [high, low] = universe.to_be_bytes();
+    Ipv4Addr::new(239, 255, high, low)
+}
+
+// An E1.31 data packet, in the layout every sACN receiver expects: a root layer identifying the
+// source, a framing layer with its name, priority and universe, and a DMP layer with the slots
+pub struct DataPacket<'a> {
+    pub cid: &'a [u8; 16],
+    pub source_name: &'a str,
+    pub priority: u8,
+    pub sequence: u8,
+    pub universe: u16,
+    pub start_code: u8,
+    pub data: &'a [u8],
+}
+
+impl DataPacket<'_> {
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let length = HEADER_LENGTH + self.data.len();
+        let mut packet = Vec::with_capacity(length);
+
+        packet.extend_from_slice(&0x0010u16.to_be_bytes());
+        packet.extend_from_slice(&0x0000u16.to_be_bytes());
+        packet.extend_from_slice(ACN_PACKET_IDENTIFIER);
+        packet.extend_from_slice(&flags_and_length(length - ROOT_LAYER));
+        packet.extend_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet.extend_from_slice(self.cid);
+
+        packet.extend_from_slice(&flags_and_length(length - FRAMING_LAYER));
+        packet.extend_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        let mut source_name = [0u8; 64];
+        let name = self.source_name.as_bytes();
+        let name_length = name.len().min(MAX_SOURCE_NAME_LENGTH);
+        source_name[..name_length].copy_from_slice(&name[..name_length]);
+        packet.extend_from_slice(&source_name);
+        packet.push(self.priority);
+        packet.extend_from_slice(&0u16.to_be_bytes()); // no synchronization universe
+        packet.push(self.sequence);
+        packet.push(0); // options: not preview data, not terminating the stream
+        packet.extend_from_slice(&self.universe.to_be_bytes());
+
+        packet.extend_from_slice(&flags_and_length(length - DMP_LAYER));
+        packet.push(VECTOR_DMP_SET_PROPERTY);
+        packet.push(DMP_ADDRESS_AND_DATA_TYPE);
+        packet.extend_from_slice(&0u16.to_be_bytes()); // first property address
+        packet.extend_from_slice(&1u16.to_be_bytes()); // address increment
+        packet.extend_from_slice(&(1 + self.data.len() as u16).to_be_bytes());
+        packet.push(self.start_code);
+        packet.extend_from_slice(self.data);
+
+        packet
+    }
+}
+
+fn flags_and_length(length: usize) -> [u8; 2] {
+    (0x7000 | length as u16).to_be_bytes()
+}
+
+// Without a configured CID, derive one from the source name, so it stays the same across restarts
+// as the standard asks. This is a 128-bit FNV-1a hash, shaped as a version 8 (custom) UUID.
+pub fn default_cid(source_name: &str) -> [u8; 16] {
+    let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+    for byte in source_name.bytes() {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(0x0000000001000000000000000000013B);
+    }
+
+    let mut cid = hash.to_be_bytes();
+    cid[6] = (cid[6] & 0x0f) | 0x80;
+    cid[8] = (cid[8] & 0x3f) | 0x80;
+    cid
+}
+
+// Sends sACN from one socket, numbering the packets for each sACN universe. Destinations without
+// their own priority use the bridge's.
+pub struct SacnOutput {
+    socket: UdpSocket,
+    source_name: String,
+    cid: [u8; 16],
+    priority: u8,
+    sequences: HashMap<u16, u8>,
+}
+
+impl SacnOutput {
+    pub fn new(socket: UdpSocket, source_name: &str, cid: [u8; 16], priority: u8) -> Self {
+        SacnOutput {
+            socket,
+            source_name: source_name.to_string(),
+            cid,
+            priority,
+            sequences: HashMap::new(),
+        }
+    }
+
+    pub fn send(&mut self, destination: &SacnDestination, start_code: u8, data: &[u8]) -> io::Result<usize> {
+        let sequence = self.sequences.entry(destination.sacn_universe).or_insert(0);
+        *sequence = sequence.wrapping_add(1);
+
+        let packet = DataPacket {
+            cid: &self.cid,
+            source_name: &self.source_name,
+            priority: destination.sacn_priority.unwrap_or(self.priority),
+            sequence: *sequence,
+            universe: destination.sacn_universe,
+            start_code,
+            data,
+        };
+        self.socket.send_to(&packet.to_bytes(), destination.sacn_socket_addr)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_data_packet() {
+        let cid = [0x11; 16];
+        let packet = DataPacket {
+            cid: &cid,
+            source_name: "bridge",
+            priority: 150,
+            sequence: 7,
+            universe: 0x0102,
+            start_code: 0,
+            data: &[10, 20, 30],
+        }.to_bytes();
+
+        assert_eq!(packet.len(), 129);
+        assert_eq!(packet[..16], [0x00, 0x10, 0x00, 0x00, 0x41, 0x53, 0x43, 0x2d, 0x45, 0x31, 0x2e, 0x31, 0x37, 0x00, 0x00, 0x00]);
+        assert_eq!(packet[16..22], [0x70, 0x71, 0x00, 0x00, 0x00, 0x04]);
+        assert_eq!(packet[22..38], cid);
+        assert_eq!(packet[38..44], [0x70, 0x5b, 0x00, 0x00, 0x00, 0x02]);
+        assert_eq!(&packet[44..51], b"bridge\0");
+        assert_eq!(packet[108..115], [150, 0x00, 0x00, 7, 0x00, 0x01, 0x02]);
+        assert_eq!(packet[115..129], [0x70, 0x0e, 0x02, 0xa1, 0x00, 0x00, 0x00, 0x01, 0x00, 0x04, 0x00, 10, 20, 30]);
+
+        // A full universe makes the familiar 638 byte packet
+        let full = DataPacket { cid: &cid, source_name: "", priority: 100, sequence: 0, universe: 1, start_code: 0, data: &[0; 512] };
+        assert_eq!(full.to_bytes().len(), 638);
+    }
+
+    #[test]
+    fn test_addresses_and_cid() {
+        assert_eq!(multicast_address(1), Ipv4Addr::new(239, 255, 0, 1));
+        assert_eq!(multicast_address(63999), Ipv4Addr::new(239, 255, 249, 255));
+
+        assert_eq!(default_cid("bridge"), default_cid("bridge"));
+        assert_ne!(default_cid("bridge"), default_cid("other bridge"));
+        assert_eq!(default_cid("bridge")[6] & 0xf0, 0x80);
+    }
+}
@@ -0,0 +1,82 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+use crate::artnet::PortAddress;
+
+// A jump back of more than this many packets is taken as the source restarting rather than reordering
+const RESTART_WINDOW: i8 = -20;
+
+// If a source has been quiet for this long, accept whatever sequence number it comes back with
+const RESTART_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Drops ArtDmx packets that arrive after a newer packet for the same universe from the same source.
+// Sequence numbers run from 1 to 255 and wrap back to 1; a sequence of 0 means the source doesn't
+// use them, so those packets are always accepted.
+#[derive(Default)]
+pub struct SequenceTracker {
+    last_seen: HashMap<(IpAddr, PortAddress), (u8, Instant)>,
+}
+
+impl SequenceTracker {
+    pub fn accept(&mut self, source: IpAddr, port_address: PortAddress, sequence: u8, now: Instant) -> bool {
+        if sequence == 0 {
+            return true;
+        }
+
+        let key = (source, port_address);
+        if let Some((last_sequence, last_time)) = self.last_seen.get(&key) {
+            let difference = sequence.wrapping_sub(*last_sequence) as i8;
+            let recent = now.duration_since(*last_time) < RESTART_TIMEOUT;
+            if recent && difference <= 0 && difference > RESTART_WINDOW {
+                return false;
+            }
+        }
+
+        self.last_seen.insert(key, (sequence, now));
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn test_sequence_tracker() {
+        let console = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 10));
+        let backup = IpAddr::V4(Ipv4Addr::new(192, 168, 1, 11));
+        let universe = PortAddress::from_flat(1).unwrap();
+        let other_universe = PortAddress::from_flat(2).unwrap();
+        let start = Instant::now();
+        let mut tracker = SequenceTracker::default();
+
+        assert!(tracker.accept(console, universe, 10, start));
+        assert!(tracker.accept(console, universe, 11, start));
+        assert!(!tracker.accept(console, universe, 9, start), "older packet should be dropped");
+        assert!(!tracker.accept(console, universe, 11, start), "repeated packet should be dropped");
+        assert!(tracker.accept(console, universe, 13, start), "skipped packets are fine");
+
+        // Each source and universe is tracked separately
+        assert!(tracker.accept(backup, universe, 5, start));
+        assert!(tracker.accept(console, other_universe, 1, start));
+
+        // Wrapping from 255 skips 0
+        let wrapping_universe = PortAddress::from_flat(3).unwrap();
+        assert!(tracker.accept(console, wrapping_universe, 254, start));
+        assert!(tracker.accept(console, wrapping_universe, 255, start));
+        assert!(tracker.accept(console, wrapping_universe, 1, start));
+        assert!(!tracker.accept(console, wrapping_universe, 254, start));
+
+        // Sequence 0 disables tracking
+        assert!(tracker.accept(console, universe, 0, start));
+        assert!(tracker.accept(console, universe, 0, start));
+
+        // A big jump back, or a long pause, means the source restarted
+        assert!(tracker.accept(console, universe, 100, start));
+        assert!(tracker.accept(console, universe, 20, start));
+        assert!(!tracker.accept(console, universe, 19, start));
+        assert!(tracker.accept(console, universe, 19, start + RESTART_TIMEOUT));
+    }
+}
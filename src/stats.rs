@@ -0,0 +1,191 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, SystemTime};
+
+// How often the frame rate of each universe is worked out
+const FRAME_RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+// Each universe and source pair is reported as its own metric, so only this many are kept apart, and
+// a flood of packets from made-up addresses can't make the list grow without end
+pub const MAX_SOURCES: usize = 256;
+
+// Counters updated by the bridge as it runs, shared with anything that reports on them
+#[derive(Debug, Default)]
+pub struct Stats {
+    pub artnet_received: AtomicU64,
+    pub artnet_out_of_order: AtomicU64,
+    pub artnet_parse_errors: AtomicU64,
+    // ArtDmx packets of any start code, by universe and the address they came from
+    pub artnet_sources: Mutex<BTreeMap<(u16, IpAddr), u64>>,
+    // ArtDmx packets from universe and source pairs seen after MAX_SOURCES others
+    pub artnet_untracked_sources: AtomicU64,
+    // Packets for universes that aren't mapped anywhere
+    pub unmapped: Mutex<BTreeMap<u16, u64>>,
+    pub universes: Mutex<BTreeMap<u16, UniverseStats>>,
+    // Keyed by a description of the destination, such as "KiNET 10.0.0.1 port 1"
+    pub destinations: Mutex<BTreeMap<String, DestinationStats>>,
+    // Frames replaced by a newer one before a stage of the pipeline got to them, by stage
+    pub superseded: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+// The last level data received for a universe, and what was sent on to its destinations after any
+// control adjustments
+#[derive(Debug, Clone, PartialEq)]
+pub struct UniverseStats {
+    pub packets: u64,
+    pub source: IpAddr,
+    pub received: SystemTime,
+    pub data: Vec<u8>,
+    pub output: Vec<u8>,
+    pub frame_rate: FrameRate,
+}
+
+// Frames per second of level data, counted over about a second at a time
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FrameRate {
+    pub fps: f64,
+    start: Option<SystemTime>,
+    frames: u32,
+}
+
+impl FrameRate {
+    fn record(&mut self, received: SystemTime) {
+        let start = *self.start.get_or_insert(received);
+        let elapsed = received.duration_since(start).unwrap_or_default();
+        if elapsed >= FRAME_RATE_INTERVAL {
+            self.fps = self.frames as f64 / elapsed.as_secs_f64();
+            self.start = Some(received);
+            self.frames = 0;
+        }
+        self.frames += 1;
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct DestinationStats {
+    pub sent: u64,
+    pub errors: u64,
+    pub last_error: Option<String>,
+}
+
+impl Stats {
+    pub fn increment(counter: &AtomicU64) {
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn get(counter: &AtomicU64) -> u64 {
+        counter.load(Ordering::Relaxed)
+    }
+
+    pub fn record_frame(&self, port_address: u16, source: IpAddr, data: &[u8], received: SystemTime) {
+        let mut universes = self.universes.lock().unwrap();
+        match universes.get_mut(&port_address) {
+            Some(universe) => {
+                universe.packets += 1;
+                universe.source = source;
+                universe.received = received;
+                universe.data.clear();
+                universe.data.extend_from_slice(data);
+                universe.frame_rate.record(received);
+            },
+            None => {
+                let mut frame_rate = FrameRate::default();
+                frame_rate.record(received);
+                universes.insert(port_address, UniverseStats { packets: 1, source, received, data: data.to_vec(), output: vec!(), frame_rate });
+            }
+        }
+    }
+
+    pub fn record_source(&self, port_address: u16, source: IpAddr) {
+        let mut sources = self.artnet_sources.lock().unwrap();
+        let full = sources.len() >= MAX_SOURCES;
+        match sources.get_mut(&(port_address, source)) {
+            Some(packets) => *packets += 1,
+            None if full => Stats::increment(&self.artnet_untracked_sources),
+            None => { sources.insert((port_address, source), 1); },
+        }
+    }
+
+    pub fn record_unmapped(&self, port_address: u16) {
+        *self.unmapped.lock().unwrap().entry(port_address).or_default() += 1;
+    }
+
+    // Only recorded for universes that have already had a frame recorded
+    pub fn record_output(&self, port_address: u16, data: &[u8]) {
+        if let Some(universe) = self.universes.lock().unwrap().get_mut(&port_address) {
+            universe.output.clear();
+            universe.output.extend_from_slice(data);
+        }
+    }
+
+    pub fn record_superseded(&self, stage: &'static str) {
+        *self.superseded.lock().unwrap().entry(stage).or_default() += 1;
+    }
+
+    pub fn record_send<T, E: fmt::Display>(&self, destination: String, result: &Result<T, E>) {
+        let mut destinations = self.destinations.lock().unwrap();
+        let stats = destinations.entry(destination).or_default();
+        match result {
+            Ok(_) => stats.sent += 1,
+            Err(e) => {
+                stats.errors += 1;
+                stats.last_error = Some(e.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record() {
+        let stats = Stats::default();
+        let source = "10.0.0.5".parse().unwrap();
+        let now = SystemTime::now();
+        stats.record_frame(1, source, &[1, 2, 3], now);
+        stats.record_frame(1, source, &[4], now);
+        stats.record_output(1, &[2]);
+        stats.record_output(2, &[2]);
+        let universes = stats.universes.lock().unwrap();
+        assert_eq!((universes[&1].packets, &universes[&1].data, &universes[&1].output), (2, &vec!(4), &vec!(2)));
+        assert!(!universes.contains_key(&2));
+        drop(universes);
+
+        stats.record_send::<(), &str>("KiNET 10.0.0.1 port 1".to_string(), &Ok(()));
+        stats.record_send::<(), &str>("KiNET 10.0.0.1 port 1".to_string(), &Err("unreachable"));
+        let destinations = stats.destinations.lock().unwrap();
+        assert_eq!(destinations["KiNET 10.0.0.1 port 1"], DestinationStats { sent: 1, errors: 1, last_error: Some("unreachable".to_string()) });
+    }
+
+    #[test]
+    fn test_record_source() {
+        let stats = Stats::default();
+        let source = "10.0.0.5".parse().unwrap();
+        for universe in 0..MAX_SOURCES as u16 {
+            stats.record_source(universe, source);
+        }
+        // Sources already seen are still counted once the limit is reached
+        stats.record_source(0, source);
+        stats.record_source(MAX_SOURCES as u16, source);
+        stats.record_source(0, "10.0.0.6".parse().unwrap());
+
+        let sources = stats.artnet_sources.lock().unwrap();
+        assert_eq!((sources.len(), sources[&(0, source)]), (MAX_SOURCES, 2));
+        assert_eq!(Stats::get(&stats.artnet_untracked_sources), 2);
+    }
+
+    #[test]
+    fn test_frame_rate() {
+        let start = SystemTime::now();
+        let mut frame_rate = FrameRate::default();
+        for frame in 0..=44 {
+            frame_rate.record(start + Duration::from_millis(frame * 25));
+        }
+        assert_eq!(frame_rate.fps, 40.0);
+    }
+}
@@ -1,6 +1,11 @@
-use artnet_protocol::{ArtCommand, PollReply, ARTNET_PROTOCOL_VERSION};
+use artnet_protocol::{ArtCommand, Output, PollReply, ARTNET_PROTOCOL_VERSION};
 use std::net::{SocketAddr, UdpSocket, Ipv4Addr};
-use anyhow::Error;
+use std::num::ParseIntError;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use anyhow::{anyhow, Error};
+use if_addrs::{IfAddr, Ifv4Addr};
+use crate::artnet::PortAddress;
 
 
 pub fn send_artnet_command(command: ArtCommand, socket: &UdpSocket, dest: &SocketAddr) -> Result<(), Error> {
@@ -9,6 +14,127 @@ pub fn send_artnet_command(command: ArtCommand, socket: &UdpSocket, dest: &Socke
     Ok(())
 }
 
+// Resolve a user-supplied bind address, which can be either a literal IPv4 address (including 0.0.0.0
+// to bind to all interfaces) or the name of a network interface such as eth1
+pub fn resolve_bind_address(address: &str) -> Result<Ipv4Addr, Error> {
+    if let Ok(ip) = Ipv4Addr::from_str(address) {
+        return Ok(ip);
+    }
+
+    for interface in if_addrs::get_if_addrs()? {
+        if interface.name == address {
+            if let IfAddr::V4(addr) = interface.addr {
+                return Ok(addr.ip);
+            }
+        }
+    }
+
+    Err(anyhow!("Could not find an IPv4 address or network interface named {}", address))
+}
+
+// The IPv4 addresses of this machine's network interfaces
+pub fn local_interfaces() -> Vec<Ifv4Addr> {
+    if_addrs::get_if_addrs().unwrap_or_default()
+        .into_iter()
+        .filter_map(|interface| match interface.addr {
+            IfAddr::V4(addr) => Some(addr),
+            _ => None,
+        })
+        .collect()
+}
+
+// Choose the local address to report to a peer when we're bound to all interfaces:
+// prefer the interface on the same subnet as the peer, then any non-loopback interface
+pub fn local_address_for_peer(peer: &SocketAddr) -> Option<Ipv4Addr> {
+    let interfaces = if_addrs::get_if_addrs().ok()?;
+    let ipv4_interfaces = interfaces.iter().filter_map(|interface| match &interface.addr {
+        IfAddr::V4(addr) => Some(addr),
+        _ => None,
+    });
+
+    let mut fallback = None;
+    for addr in ipv4_interfaces {
+        if let SocketAddr::V4(peer) = peer {
+            let mask = u32::from(addr.netmask);
+            if u32::from(addr.ip) & mask == u32::from(*peer.ip()) & mask {
+                return Some(addr.ip);
+            }
+        }
+        if fallback.is_none() && !addr.ip.is_loopback() {
+            fallback = Some(addr.ip);
+        }
+    }
+
+    fallback
+}
+
+// Parse a decimal or 0x-prefixed hexadecimal number
+pub fn parse_u16(value: &str) -> Result<u16, ParseIntError> {
+    match value.strip_prefix("0x").or_else(|| value.strip_prefix("0X")) {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => value.parse::<u16>(),
+    }
+}
+
+// Parse a flat Art-Net Port-Address from 0 to 32767, as used in control messages and URLs
+pub fn parse_universe(universe: &str) -> Result<u16, Error> {
+    let universe = universe.parse::<u16>().map_err(|_| anyhow!("Could not understand universe {}", universe))?;
+    Ok(PortAddress::from_flat(universe)?.value())
+}
+
+// Parse a UUID written as 32 hex digits, with or without the usual hyphens
+pub fn parse_uuid(value: &str) -> Result<[u8; 16], Error> {
+    let digits: String = value.chars().filter(|c| *c != '-').collect();
+    if digits.len() != 32 || !digits.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(anyhow!("Could not understand {} as a UUID", value));
+    }
+
+    let mut uuid = [0u8; 16];
+    for (i, byte) in uuid.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)?;
+    }
+    Ok(uuid)
+}
+
+// Lets a repeated log message through at most once per interval
+pub struct RateLimit {
+    interval: Duration,
+    last: Option<Instant>,
+}
+
+impl RateLimit {
+    pub fn new(interval: Duration) -> Self {
+        RateLimit { interval, last: None }
+    }
+
+    pub fn ready(&mut self, now: Instant) -> bool {
+        match self.last {
+            Some(last) if now.duration_since(last) < self.interval => false,
+            _ => {
+                self.last = Some(now);
+                true
+            }
+        }
+    }
+}
+
+// Copies a universe of RGB pixels into its place in a frame, growing the frame if needed. Pixels
+// the universe covers but didn't send data for are turned off.
+pub fn write_pixels(frame: &mut Vec<u8>, pixel_offset: usize, pixels: Option<usize>, data: &[u8]) {
+    let pixels = pixels.unwrap_or(data.len() / 3);
+    let start = pixel_offset * 3;
+    let end = start + pixels * 3;
+    if frame.len() < end {
+        frame.resize(end, 0);
+    }
+
+    let copied = data.len().min(pixels * 3);
+    frame[start..start + copied].copy_from_slice(&data[..copied]);
+    for channel in &mut frame[start + copied..end] {
+        *channel = 0;
+    }
+}
+
 // Fake an implementation of the Default trait for PollReply
 // Implementing Default would violate the orphan rules for trait implementaitons
 pub fn default_poll_reply() -> PollReply {
@@ -42,3 +168,82 @@ pub fn default_poll_reply() -> PollReply {
         filler: [0; 26],
     }
 }
+
+// An ArtDmx packet for relaying a universe to another node. ArtDmx data has to be an even number of
+// channels, so odd lengths are padded. artnet_protocol 0.2.0 gets the byte order of the length wrong
+// when it writes it out, just as it does when parsing, so it's swapped to come out right on the wire.
+pub fn dmx_output(port_address: PortAddress, sequence: u8, physical: u8, data: &[u8]) -> ArtCommand {
+    let mut data = data.to_vec();
+    if data.len() % 2 == 1 {
+        data.push(0);
+    }
+
+    ArtCommand::Output(Output {
+        version: ARTNET_PROTOCOL_VERSION,
+        sequence,
+        physical,
+        subnet: port_address.value(),
+        length: (data.len() as u16).swap_bytes(),
+        data,
+    })
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve_bind_address() {
+        assert_eq!(resolve_bind_address("192.168.1.1").unwrap(), Ipv4Addr::new(192, 168, 1, 1));
+        assert_eq!(resolve_bind_address("0.0.0.0").unwrap(), Ipv4Addr::UNSPECIFIED);
+        resolve_bind_address("no-such-interface-name").expect_err("Expected unknown interface to fail");
+        resolve_bind_address("10.0.0.266").expect_err("Expected invalid address to fail");
+    }
+
+    #[test]
+    fn test_write_pixels() {
+        let mut frame = vec!();
+        write_pixels(&mut frame, 2, None, &[1, 2, 3, 4, 5, 6, 7]);
+        assert_eq!(frame, vec!(0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6));
+
+        // A second universe further along the strip leaves the first alone
+        write_pixels(&mut frame, 4, Some(2), &[9, 9, 9]);
+        assert_eq!(frame, vec!(0, 0, 0, 0, 0, 0, 1, 2, 3, 4, 5, 6, 9, 9, 9, 0, 0, 0));
+
+        // Sending fewer pixels than configured turns off the rest
+        write_pixels(&mut frame, 2, Some(2), &[8, 8, 8]);
+        assert_eq!(frame, vec!(0, 0, 0, 0, 0, 0, 8, 8, 8, 0, 0, 0, 9, 9, 9, 0, 0, 0));
+    }
+
+    #[test]
+    fn test_dmx_output() {
+        match dmx_output(PortAddress::from_flat(0x0123).unwrap(), 9, 1, &[1, 2, 3]) {
+            ArtCommand::Output(output) => {
+                assert_eq!(output.subnet, 0x0123);
+                assert_eq!((output.sequence, output.physical), (9, 1));
+                assert_eq!(output.length.swap_bytes(), 4);
+                assert_eq!(output.data, vec!(1, 2, 3, 0));
+            },
+            command => panic!("Expected an ArtDmx packet, got {:?}", command),
+        }
+    }
+
+    #[test]
+    fn test_parse_u16() {
+        assert_eq!(parse_u16("256"), Ok(256));
+        assert_eq!(parse_u16("0x0100"), Ok(256));
+        assert_eq!(parse_u16("0XFFFF"), Ok(0xFFFF));
+        parse_u16("0x10000").expect_err("Expected overflow to fail");
+        parse_u16("ff").expect_err("Expected hex without a prefix to fail");
+    }
+
+    #[test]
+    fn test_parse_uuid() {
+        let uuid = [0x6b, 0xa7, 0xb8, 0x10, 0x9d, 0xad, 0x11, 0xd1, 0x80, 0xb4, 0x00, 0xc0, 0x4f, 0xd4, 0x30, 0xc8];
+        assert_eq!(parse_uuid("6ba7b810-9dad-11d1-80b4-00c04fd430c8").unwrap(), uuid);
+        assert_eq!(parse_uuid("6BA7B8109DAD11D180B400C04FD430C8").unwrap(), uuid);
+        parse_uuid("6ba7b810-9dad-11d1-80b4").expect_err("Expected a short UUID to fail");
+        parse_uuid("6ba7b810-9dad-11d1-80b4-00c04fd430cg").expect_err("Expected a non-hex UUID to fail");
+    }
+}
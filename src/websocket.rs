@@ -0,0 +1,205 @@
+use std::convert::TryInto;
+use std::io::{self, Read, Write};
+
+// Appended to the client's key to make the accept key, from RFC 6455
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+// Clients only send small control messages to the bridge
+const MAX_MESSAGE_LENGTH: u64 = 4096;
+
+const FIN: u8 = 0x80;
+const MASKED: u8 = 0x80;
+pub const TEXT: u8 = 0x1;
+pub const BINARY: u8 = 0x2;
+pub const CLOSE: u8 = 0x8;
+pub const PING: u8 = 0x9;
+pub const PONG: u8 = 0xa;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Text(String),
+    Binary(Vec<u8>),
+    Ping(Vec<u8>),
+    Pong,
+    Close,
+}
+
+// The Sec-WebSocket-Accept value that completes the handshake for a client's Sec-WebSocket-Key
+pub fn accept_key(key: &str) -> String {
+    base64(&sha1(format!("{}{}", key.trim(), ACCEPT_GUID).as_bytes()))
+}
+
+pub fn handshake_response(key: &str) -> String {
+    format!("HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key))
+}
+
+// Frames from the server aren't masked, and are never split
+pub fn write_frame(writer: &mut impl Write, opcode: u8, payload: &[u8]) -> io::Result<()> {
+    let mut header = vec!(FIN | opcode);
+    match payload.len() {
+        length if length < 126 => header.push(length as u8),
+        length if length <= 0xffff => {
+            header.push(126);
+            header.extend_from_slice(&(length as u16).to_be_bytes());
+        },
+        length => {
+            header.push(127);
+            header.extend_from_slice(&(length as u64).to_be_bytes());
+        }
+    }
+    writer.write_all(&header)?;
+    writer.write_all(payload)?;
+    writer.flush()
+}
+
+// Reads one message from a client. Frames from clients are always masked. Fragmented messages
+// aren't needed for anything the bridge accepts, so they're rejected.
+pub fn read_message(reader: &mut impl Read) -> io::Result<Message> {
+    let invalid = |message: &str| io::Error::new(io::ErrorKind::InvalidData, message.to_string());
+
+    let mut header = [0u8; 2];
+    reader.read_exact(&mut header)?;
+    if header[0] & FIN == 0 || header[0] & 0x0f == 0 {
+        return Err(invalid("Fragmented WebSocket messages aren't supported"));
+    }
+    if header[1] & MASKED == 0 {
+        return Err(invalid("WebSocket frames from clients must be masked"));
+    }
+
+    let length = match header[1] & 0x7f {
+        126 => {
+            let mut length = [0u8; 2];
+            reader.read_exact(&mut length)?;
+            u16::from_be_bytes(length) as u64
+        },
+        127 => {
+            let mut length = [0u8; 8];
+            reader.read_exact(&mut length)?;
+            u64::from_be_bytes(length)
+        },
+        length => length as u64,
+    };
+    if length > MAX_MESSAGE_LENGTH {
+        return Err(invalid("WebSocket message is too long"));
+    }
+
+    let mut mask = [0u8; 4];
+    reader.read_exact(&mut mask)?;
+    let mut payload = vec!(0u8; length as usize);
+    reader.read_exact(&mut payload)?;
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+
+    match header[0] & 0x0f {
+        TEXT => String::from_utf8(payload).map(Message::Text).map_err(|_| invalid("WebSocket text isn't UTF-8")),
+        BINARY => Ok(Message::Binary(payload)),
+        CLOSE => Ok(Message::Close),
+        PING => Ok(Message::Ping(payload)),
+        PONG => Ok(Message::Pong),
+        _ => Err(invalid("Unknown WebSocket opcode")),
+    }
+}
+
+fn sha1(data: &[u8]) -> [u8; 20] {
+    let mut state: [u32; 5] = [0x67452301, 0xefcdab89, 0x98badcfe, 0x10325476, 0xc3d2e1f0];
+
+    let mut message = data.to_vec();
+    message.push(0x80);
+    while message.len() % 64 != 56 {
+        message.push(0);
+    }
+    message.extend_from_slice(&(data.len() as u64 * 8).to_be_bytes());
+
+    for block in message.chunks(64) {
+        let mut words = [0u32; 80];
+        for (word, bytes) in words.iter_mut().zip(block.chunks(4)) {
+            *word = u32::from_be_bytes(bytes.try_into().unwrap());
+        }
+        for index in 16..80 {
+            words[index] = (words[index - 3] ^ words[index - 8] ^ words[index - 14] ^ words[index - 16]).rotate_left(1);
+        }
+
+        let [mut a, mut b, mut c, mut d, mut e] = state;
+        for (index, word) in words.iter().enumerate() {
+            let (f, k) = match index {
+                0..=19 => ((b & c) | (!b & d), 0x5a827999),
+                20..=39 => (b ^ c ^ d, 0x6ed9eba1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8f1bbcdc),
+                _ => (b ^ c ^ d, 0xca62c1d6),
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*word);
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+        for (value, add) in state.iter_mut().zip(&[a, b, c, d, e]) {
+            *value = value.wrapping_add(*add);
+        }
+    }
+
+    let mut digest = [0u8; 20];
+    for (bytes, value) in digest.chunks_mut(4).zip(&state) {
+        bytes.copy_from_slice(&value.to_be_bytes());
+    }
+    digest
+}
+
+fn base64(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bytes = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let group = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+        for index in 0..4 {
+            if index <= chunk.len() {
+                encoded.push(ALPHABET[(group >> (18 - index * 6)) as usize & 0x3f] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_accept_key() {
+        // The example from RFC 6455
+        assert_eq!(accept_key("dGhlIHNhbXBsZSBub25jZQ=="), "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=");
+        assert_eq!(base64(b"ab"), "YWI=");
+        assert_eq!(base64(b"a"), "YQ==");
+    }
+
+    #[test]
+    fn test_frames() {
+        let mut written = vec!();
+        write_frame(&mut written, BINARY, &[1, 2, 3]).unwrap();
+        assert_eq!(written, vec!(0x82, 3, 1, 2, 3));
+        let mut written = vec!();
+        write_frame(&mut written, TEXT, &[0; 300]).unwrap();
+        assert_eq!(written[..4], [0x81, 126, 1, 44]);
+
+        // "Hello", masked, from RFC 6455
+        let hello = [0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58];
+        assert_eq!(read_message(&mut Cursor::new(&hello[..])).unwrap(), Message::Text("Hello".to_string()));
+        assert_eq!(read_message(&mut Cursor::new(&[0x88, 0x80, 0, 0, 0, 0][..])).unwrap(), Message::Close);
+
+        let bad_cases: Vec<&[u8]> = vec!(
+            &[0x81, 0x05, 0x48, 0x65, 0x6c, 0x6c, 0x6f], // not masked
+            &[0x01, 0x80, 0, 0, 0, 0], // fragmented
+            &[0x82, 0xff, 0, 0, 0, 0, 0, 1, 0, 0], // too long
+            &[0x81, 0x85, 0, 0, 0, 0, 0x48], // truncated
+        );
+        for case in bad_cases {
+            read_message(&mut Cursor::new(case)).expect_err(&format!("Expected {:?} to fail", case));
+        }
+    }
+}